@@ -0,0 +1,392 @@
+use crate::database::{
+    Biases, Database, EarlyStopping, FactorModel, Loss, Penalty, RegularizationScaling,
+    TrainingParameters,
+};
+use crate::evaluation::Split;
+use crate::report::{Record, TrainingLog};
+use nalgebra::{DMatrix, DVector};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+/// Trains a factor model with alternating least squares. With the article factors fixed, the best
+/// factors of every user are the solution of a small regularized least-squares problem, and vice
+/// versa. Each iteration solves these problems for all users and then for all articles, so unlike
+/// gradient descent no learning rate is needed and all factors are learned at once. With biases,
+/// every factor vector is extended by the bias of its user or article, paired with a constant 1
+/// on the other side.
+pub(crate) fn train(
+    database: Database,
+    parameters: &TrainingParameters,
+    held_out: Split,
+    log: &TrainingLog,
+) -> FactorModel {
+    let TrainingParameters {
+        regularization_scaling,
+        biases: learn_biases,
+        ..
+    } = *parameters;
+    let (user_penalty, article_penalty) = (parameters.user_penalty(), parameters.article_penalty());
+
+    let global_mean = if learn_biases {
+        database.mean_vote().unwrap_or(0.0)
+    } else {
+        0.0
+    };
+    let user_votes = database.votes_by_user();
+
+    let (database, state, held_out) = alternate(
+        database,
+        parameters,
+        held_out,
+        global_mean,
+        log,
+        |database, state| {
+            solve_all(
+                &user_votes,
+                &state.article_factors,
+                &state.biases.article,
+                global_mean,
+                learn_biases,
+                user_penalty,
+                regularization_scaling,
+                &mut state.user_factors,
+                &mut state.biases.user,
+            );
+            solve_all(
+                database.article_votes(),
+                &state.user_factors,
+                &state.biases.user,
+                global_mean,
+                learn_biases,
+                article_penalty,
+                regularization_scaling,
+                &mut state.article_factors,
+                &mut state.biases.article,
+            );
+        },
+    );
+    FactorModel::new(
+        database,
+        state.user_factors,
+        state.article_factors,
+        learn_biases.then_some(state.biases),
+        Loss::Squared,
+        held_out,
+    )
+}
+
+/// Trains a factor model on implicit feedback with the weighted alternating least squares of Hu,
+/// Koren and Volinsky. Every pair of user and article is treated as observed: pairs with a vote
+/// have a confidence of `1 + confidence` and all other pairs a confidence of 1. The preference of
+/// a pair is the vote shifted by one, so it is 2 for upvotes and 0 for downvotes and unvoted
+/// articles. Together with a global mean of -1, predictions are therefore on the same scale as
+/// the votes, which keeps them comparable to the explicit models.
+///
+/// Solving for a user requires the sum over all articles, but the articles without a vote all
+/// have confidence 1, so that part of the sum is the same for every user. It is computed once
+/// per iteration, and the remaining work scales with the number of votes instead of the number
+/// of user and article pairs. The same holds for articles. Biases are not learned.
+pub(crate) fn train_implicit(
+    database: Database,
+    parameters: &TrainingParameters,
+    held_out: Split,
+    log: &TrainingLog,
+) -> FactorModel {
+    let TrainingParameters { confidence, .. } = *parameters;
+
+    let user_votes = database.votes_by_user();
+
+    let (database, state, held_out) = alternate(
+        database,
+        parameters,
+        held_out,
+        -1.0,
+        log,
+        |database, state| {
+            solve_all_implicit(
+                &user_votes,
+                &state.article_factors,
+                confidence,
+                parameters.user_penalty().factors,
+                &mut state.user_factors,
+            );
+            solve_all_implicit(
+                database.article_votes(),
+                &state.user_factors,
+                confidence,
+                parameters.article_penalty().factors,
+                &mut state.article_factors,
+            );
+        },
+    );
+    FactorModel::new(
+        database,
+        state.user_factors,
+        state.article_factors,
+        Some(state.biases),
+        Loss::Squared,
+        held_out,
+    )
+}
+
+/// The parameters of a factor model during alternating least squares.
+#[derive(Clone, Debug)]
+struct AlsState {
+    user_factors: DMatrix<f64>,
+    article_factors: DMatrix<f64>,
+    biases: Biases,
+}
+
+impl AlsState {
+    /// Predicts the vote of a user for an article.
+    fn predict(&self, user_id: usize, article_id: usize) -> f64 {
+        self.biases.predict(user_id, article_id)
+            + self
+                .user_factors
+                .row(user_id)
+                .dot(&self.article_factors.row(article_id))
+    }
+}
+
+/// Runs the iterations of alternating least squares, where `iterate` solves for all users and
+/// then for all articles of the database. The article factors are initialized from the seed in
+/// `parameters`, the user factors are solved first and need no initial value. Early stopping and
+/// progress reports work like in the gradient descent trainers, except that whole iterations are
+/// stopped. Returns the database, the trained parameters and the held out votes.
+fn alternate(
+    database: Database,
+    parameters: &TrainingParameters,
+    held_out: Split,
+    global_mean: f64,
+    log: &TrainingLog,
+    mut iterate: impl FnMut(&Database, &mut AlsState),
+) -> (Database, AlsState, Split) {
+    let TrainingParameters {
+        latent_factors,
+        iterations,
+        seed,
+        early_stopping,
+        ..
+    } = *parameters;
+    let initialization = parameters.initialization();
+
+    // The initial article factors have to be random, otherwise all factors stay identical.
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut state = AlsState {
+        user_factors: DMatrix::zeros(database.user_count(), latent_factors),
+        article_factors: initialization.matrix(database.article_count(), latent_factors, &mut rng),
+        biases: Biases::new(global_mean, database.user_count(), database.article_count()),
+    };
+
+    // the lowest validation error together with the parameters achieving it
+    let mut best_validation_error = f64::INFINITY;
+    let mut best_state = None;
+    let mut iterations_without_improvement = 0;
+
+    for iteration in 0..iterations {
+        let now = std::time::Instant::now();
+        iterate(&database, &mut state);
+        let duration = now.elapsed();
+
+        // the errors cost a pass over the votes, so they are only computed when reported
+        let training_error = (log.verbose || log.is_recording())
+            .then(|| {
+                Database::mean_square_error(database.article_votes(), |user_id, article_id| {
+                    state.predict(user_id, article_id)
+                })
+            })
+            .flatten();
+        let validation_error = (early_stopping.is_some()
+            || log.is_recording() && held_out.has_validation())
+        .then(|| {
+            Database::mean_square_error(&held_out.validation, |user_id, article_id| {
+                state.predict(user_id, article_id)
+            })
+        })
+        .flatten();
+        if let Some(training_error) = training_error {
+            log.record(Record {
+                factor: None,
+                iteration,
+                training_loss: training_error,
+                validation_loss: validation_error,
+                gradient_norm: None,
+                duration,
+            });
+        }
+
+        if log.verbose {
+            println!(
+                "Iteration {}/{} finished in {}ms.",
+                iteration + 1,
+                iterations,
+                duration.as_millis()
+            );
+            if let Some(training_error) = training_error {
+                println!("Mean square error: {}", training_error);
+            }
+        }
+
+        if let (Some(EarlyStopping { patience, .. }), Some(validation_error)) =
+            (early_stopping, validation_error)
+        {
+            if log.verbose {
+                println!("Validation mean square error: {}", validation_error);
+            }
+            if validation_error < best_validation_error {
+                best_validation_error = validation_error;
+                best_state = Some(state.clone());
+                iterations_without_improvement = 0;
+            } else {
+                iterations_without_improvement += 1;
+                if iterations_without_improvement >= patience {
+                    if log.verbose {
+                        println!("Validation error stopped improving, stopping training.");
+                    }
+                    break;
+                }
+            }
+        }
+    }
+
+    // restore the parameters with the lowest validation error
+    if let Some(best_state) = best_state {
+        state = best_state;
+    }
+
+    if log.verbose {
+        println!("Training finished.");
+    }
+
+    (database, state, held_out)
+}
+
+/// Solves the factors of all entities on one side (all users or all articles) in parallel, while
+/// the `fixed_factors` and `fixed_biases` of the other side stay fixed. `votes` lists the votes of
+/// each entity as tuples of the id on the other side and the vote. The factors and biases of each
+/// entity are regularized by the strengths in `penalty`, scaled by its number of votes. The
+/// solutions are written to `factors` and, if `learn_biases` is set, to `biases`.
+#[allow(clippy::too_many_arguments)]
+fn solve_all(
+    votes: &[Vec<(usize, bool)>],
+    fixed_factors: &DMatrix<f64>,
+    fixed_biases: &DVector<f64>,
+    global_mean: f64,
+    learn_biases: bool,
+    penalty: Penalty,
+    scaling: RegularizationScaling,
+    factors: &mut DMatrix<f64>,
+    biases: &mut DVector<f64>,
+) {
+    let latent_factors = fixed_factors.ncols();
+    let dimension = latent_factors + usize::from(learn_biases);
+
+    let solutions = votes
+        .par_iter()
+        .map(|votes| {
+            // without votes, the regularization alone keeps all parameters at zero
+            if votes.is_empty() {
+                return DVector::zeros(dimension);
+            }
+
+            // Ridge regression: minimize the square error of the votes plus the regularization
+            // term, which is scaled by the number of votes like in the gradient descent trainers.
+            // The bias may be regularized with a different strength than the factors.
+            let mut system = DMatrix::<f64>::zeros(dimension, dimension);
+            let mut target = DVector::<f64>::zeros(dimension);
+            let mut features = DVector::<f64>::zeros(dimension);
+            for &(other_id, vote) in votes {
+                let vote = if vote { 1.0 } else { -1.0 };
+                features
+                    .rows_mut(0, latent_factors)
+                    .copy_from(&fixed_factors.row(other_id).transpose());
+                if learn_biases {
+                    features[latent_factors] = 1.0;
+                }
+                let residual = vote - global_mean - fixed_biases[other_id];
+                system.ger(1.0, &features, &features, 1.0);
+                target.axpy(residual, &features, 1.0);
+            }
+            let scale = scaling.scale(votes.len() as f64);
+            for i in 0..latent_factors {
+                system[(i, i)] += penalty.factors * scale;
+            }
+            if learn_biases {
+                system[(latent_factors, latent_factors)] += penalty.biases * scale;
+            }
+
+            // Without regularization, the system is singular if there are fewer votes than
+            // parameters. The least-squares solution of minimal norm is used then.
+            match system.clone().cholesky() {
+                Some(cholesky) => cholesky.solve(&target),
+                None => system
+                    .svd(true, true)
+                    .solve(&target, 1e-12)
+                    .expect("Failed to solve least-squares problem."),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    for (id, solution) in solutions.iter().enumerate() {
+        factors
+            .row_mut(id)
+            .copy_from(&solution.rows(0, latent_factors).transpose());
+        if learn_biases {
+            biases[id] = solution[latent_factors];
+        }
+    }
+}
+
+/// Solves the factors of all entities on one side for implicit ALS, while the `fixed_factors` of
+/// the other side stay fixed. `votes` lists the votes of each entity as tuples of the id on the
+/// other side and the vote. The solutions are written to `factors`.
+fn solve_all_implicit(
+    votes: &[Vec<(usize, bool)>],
+    fixed_factors: &DMatrix<f64>,
+    confidence: f64,
+    regularization: f64,
+    factors: &mut DMatrix<f64>,
+) {
+    let latent_factors = fixed_factors.ncols();
+
+    // the part of every system that all pairs contribute with confidence 1
+    let mut gram = fixed_factors.tr_mul(fixed_factors);
+    for i in 0..latent_factors {
+        gram[(i, i)] += regularization;
+    }
+
+    let solutions = votes
+        .par_iter()
+        .map(|votes| {
+            // without votes, all preferences are zero and so is the solution
+            if votes.is_empty() {
+                return DVector::zeros(latent_factors);
+            }
+
+            // Pairs with a vote add their extra confidence to the system. Only upvotes have a
+            // nonzero preference and contribute to the target.
+            let mut system = gram.clone();
+            let mut target = DVector::<f64>::zeros(latent_factors);
+            for &(other_id, vote) in votes {
+                let features = fixed_factors.row(other_id).transpose();
+                system.ger(confidence, &features, &features, 1.0);
+                if vote {
+                    target.axpy(2.0 * (1.0 + confidence), &features, 1.0);
+                }
+            }
+
+            match system.clone().cholesky() {
+                Some(cholesky) => cholesky.solve(&target),
+                None => system
+                    .svd(true, true)
+                    .solve(&target, 1e-12)
+                    .expect("Failed to solve least-squares problem."),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    for (id, solution) in solutions.iter().enumerate() {
+        factors.row_mut(id).copy_from(&solution.transpose());
+    }
+}
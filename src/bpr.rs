@@ -0,0 +1,274 @@
+use crate::database::{Biases, Database, FactorModel, Loss, NegativeSampling, TrainingParameters};
+use crate::evaluation::Split;
+use crate::hogwild::SharedMatrix;
+use crate::optimizer::SharedMoments;
+use crate::report::{Record, TrainingLog};
+use nalgebra::DMatrix;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+/// The number of pairs each parallel task samples and trains on per iteration.
+const CHUNK_SIZE: usize = 4096;
+
+/// How often a negative article is drawn before the pair is skipped, for users who voted on
+/// (nearly) every article.
+const SAMPLING_ATTEMPTS: usize = 64;
+
+/// Trains a factor model with Bayesian personalized ranking after Rendle et al. Instead of
+/// predicting votes, BPR maximizes the probability that each user ranks an upvoted article above
+/// a sampled negative article, so the scores of the model are only meaningful relative to each
+/// other. Every iteration trains on as many sampled pairs as there are upvotes. The pairs are
+/// trained in parallel without locking, in the Hogwild style: concurrent updates of the same
+/// factors may overwrite each other, which is rare enough for sparse votes not to hurt.
+/// Deterministic training trains the pairs one chunk after another instead. With
+/// biases, an article bias is learned as well; user biases do not affect a user's ranking.
+pub(crate) fn train(
+    database: Database,
+    parameters: &TrainingParameters,
+    held_out: Split,
+    log: &TrainingLog,
+) -> FactorModel {
+    let TrainingParameters {
+        latent_factors,
+        iterations,
+        learning_rate,
+        optimizer,
+        biases: learn_biases,
+        negative_sampling,
+        seed,
+        deterministic,
+        ..
+    } = *parameters;
+    let initialization = parameters.initialization();
+
+    let user_votes = database.votes_by_user();
+    let upvotes = user_votes
+        .iter()
+        .enumerate()
+        .flat_map(|(user_id, votes)| {
+            votes
+                .iter()
+                .filter(|&&(_, vote)| vote)
+                .map(move |&(article_id, _)| (user_id, article_id))
+        })
+        .collect::<Vec<_>>();
+    let downvotes = user_votes
+        .iter()
+        .map(|votes| {
+            votes
+                .iter()
+                .filter(|&&(_, vote)| !vote)
+                .map(|&(article_id, _)| article_id)
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let user_factors =
+        SharedMatrix::new(&initialization.matrix(database.user_count(), latent_factors, &mut rng));
+    let article_factors = SharedMatrix::new(&initialization.matrix(
+        database.article_count(),
+        latent_factors,
+        &mut rng,
+    ));
+    let article_biases = SharedMatrix::new(&DMatrix::zeros(database.article_count(), 1));
+    let user_moments = SharedMoments::new(optimizer, database.user_count(), latent_factors);
+    let article_moments = SharedMoments::new(optimizer, database.article_count(), latent_factors);
+    let article_bias_moments = SharedMoments::new(optimizer, database.article_count(), 1);
+
+    let (user_penalty, article_penalty) = (parameters.user_penalty(), parameters.article_penalty());
+    let sampler = Sampler {
+        user_votes: &user_votes,
+        downvotes: &downvotes,
+        articles: database.article_count(),
+        negative_sampling,
+    };
+    let chunks = upvotes.len().div_ceil(CHUNK_SIZE);
+
+    for iteration in 0..iterations {
+        let now = std::time::Instant::now();
+        let train_chunk = |chunk: usize| {
+            let mut rng =
+                StdRng::seed_from_u64(seed.wrapping_add((iteration * chunks + chunk + 1) as u64));
+            let samples = CHUNK_SIZE.min(upvotes.len() - chunk * CHUNK_SIZE);
+            let mut user = vec![0.0; latent_factors];
+            let (mut positive, mut negative) = (user.clone(), user.clone());
+            let mut log_likelihood = 0.0;
+            let mut count = 0;
+
+            for _ in 0..samples {
+                let (user_id, positive_id) = upvotes[rng.gen_range(0..upvotes.len())];
+                let Some(negative_id) = sampler.negative(user_id, &mut rng) else {
+                    continue;
+                };
+
+                user_factors.read_row(user_id, &mut user);
+                article_factors.read_row(positive_id, &mut positive);
+                article_factors.read_row(negative_id, &mut negative);
+
+                // the difference of the scores of the positive and the negative article
+                let mut difference = user
+                    .iter()
+                    .zip(positive.iter().zip(&negative))
+                    .map(|(u, (p, n))| u * (p - n))
+                    .sum::<f64>();
+                if learn_biases {
+                    difference +=
+                        article_biases.get(positive_id, 0) - article_biases.get(negative_id, 0);
+                }
+                log_likelihood -= (-difference).exp().ln_1p();
+                count += 1;
+
+                // gradient ascent on the log-likelihood of ranking the pair correctly
+                let weight = 1.0 / (1.0 + difference.exp());
+                for factor in 0..latent_factors {
+                    let (u, p, n) = (user[factor], positive[factor], negative[factor]);
+                    user_factors.add(
+                        user_id,
+                        factor,
+                        user_moments.update(
+                            learning_rate,
+                            user_id,
+                            factor,
+                            weight * (p - n) - user_penalty.factors * u,
+                        ),
+                    );
+                    article_factors.add(
+                        positive_id,
+                        factor,
+                        article_moments.update(
+                            learning_rate,
+                            positive_id,
+                            factor,
+                            weight * u - article_penalty.factors * p,
+                        ),
+                    );
+                    article_factors.add(
+                        negative_id,
+                        factor,
+                        article_moments.update(
+                            learning_rate,
+                            negative_id,
+                            factor,
+                            -weight * u - article_penalty.factors * n,
+                        ),
+                    );
+                }
+                user_moments.finish_row(user_id);
+                article_moments.finish_row(positive_id);
+                article_moments.finish_row(negative_id);
+                if learn_biases {
+                    let positive_bias = article_biases.get(positive_id, 0);
+                    let negative_bias = article_biases.get(negative_id, 0);
+                    article_biases.add(
+                        positive_id,
+                        0,
+                        article_bias_moments.update(
+                            learning_rate,
+                            positive_id,
+                            0,
+                            weight - article_penalty.biases * positive_bias,
+                        ),
+                    );
+                    article_biases.add(
+                        negative_id,
+                        0,
+                        article_bias_moments.update(
+                            learning_rate,
+                            negative_id,
+                            0,
+                            -weight - article_penalty.biases * negative_bias,
+                        ),
+                    );
+                    article_bias_moments.finish_row(positive_id);
+                    article_bias_moments.finish_row(negative_id);
+                }
+            }
+            (log_likelihood, count)
+        };
+        let sum = |a: (f64, usize), b: (f64, usize)| (a.0 + b.0, a.1 + b.1);
+        let (log_likelihood, count) = if deterministic {
+            (0..chunks).map(train_chunk).fold((0.0, 0), sum)
+        } else {
+            (0..chunks)
+                .into_par_iter()
+                .map(train_chunk)
+                .reduce(|| (0.0, 0), sum)
+        };
+
+        let duration = now.elapsed();
+        log.record(Record {
+            factor: None,
+            iteration,
+            training_loss: -log_likelihood / count as f64,
+            validation_loss: None,
+            gradient_norm: None,
+            duration,
+        });
+
+        if log.verbose {
+            println!(
+                "Iteration {}/{} finished in {}ms.",
+                iteration + 1,
+                iterations,
+                duration.as_millis()
+            );
+            println!("Mean log-likelihood: {}", log_likelihood / count as f64);
+        }
+    }
+
+    if log.verbose {
+        println!("Training finished.");
+    }
+
+    let biases = learn_biases.then(|| {
+        let mut biases = Biases::new(0.0, database.user_count(), database.article_count());
+        biases.article = article_biases.to_matrix().column(0).into_owned();
+        biases
+    });
+    FactorModel::new(
+        database,
+        user_factors.to_matrix(),
+        article_factors.to_matrix(),
+        biases,
+        Loss::Squared,
+        held_out,
+    )
+}
+
+/// Draws the negative article of a pair according to the negative sampling.
+struct Sampler<'a> {
+    /// All votes of each user, sorted by article id.
+    user_votes: &'a [Vec<(usize, bool)>],
+
+    /// The downvoted articles of each user.
+    downvotes: &'a [Vec<usize>],
+
+    /// The number of articles.
+    articles: usize,
+
+    negative_sampling: NegativeSampling,
+}
+
+impl Sampler<'_> {
+    /// Draws a negative article for the user, or `None` if no suitable article was found.
+    fn negative(&self, user_id: usize, rng: &mut StdRng) -> Option<usize> {
+        let downvotes = &self.downvotes[user_id];
+        if self.negative_sampling == NegativeSampling::Downvoted && !downvotes.is_empty() {
+            return Some(downvotes[rng.gen_range(0..downvotes.len())]);
+        }
+
+        let votes = &self.user_votes[user_id];
+        (0..SAMPLING_ATTEMPTS)
+            .map(|_| rng.gen_range(0..self.articles))
+            .find(|&article_id| {
+                match votes.binary_search_by_key(&article_id, |&(article_id, _)| article_id) {
+                    Ok(index) => {
+                        self.negative_sampling != NegativeSampling::Unvoted && !votes[index].1
+                    }
+                    Err(_) => true,
+                }
+            })
+    }
+}
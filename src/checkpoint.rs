@@ -0,0 +1,74 @@
+use crate::database::{Database, Progress, TrainingParameters};
+use crate::evaluation::Split;
+use crate::storage::Storage;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Default time between two checkpoints in seconds.
+pub(crate) const DEFAULT_CHECKPOINT_INTERVAL: u64 = 600;
+
+/// Where and how often a training run saves checkpoints.
+pub(crate) struct Checkpointing<'a> {
+    pub(crate) storage: &'a Storage,
+
+    /// The minimum time between two checkpoints.
+    pub(crate) interval: Duration,
+}
+
+/// Everything needed to resume an interrupted training: the parameters, the votes it trains on
+/// and those held out, and how far it has come.
+#[derive(Deserialize)]
+pub(crate) struct Checkpoint {
+    pub(crate) parameters: TrainingParameters,
+    pub(crate) database: Database,
+    pub(crate) held_out: Split,
+    pub(crate) progress: Progress,
+}
+
+/// A [`Checkpoint`] borrowing its contents from the running training, so saving it copies nothing.
+#[derive(Serialize)]
+struct CheckpointRef<'a> {
+    parameters: &'a TrainingParameters,
+    database: &'a Database,
+    held_out: &'a Split,
+    progress: &'a Progress,
+}
+
+impl Checkpointing<'_> {
+    /// Saves a checkpoint to the checkpoint file, replacing the previous one.
+    pub(crate) fn save(
+        &self,
+        parameters: &TrainingParameters,
+        database: &Database,
+        held_out: &Split,
+        progress: &Progress,
+    ) {
+        let checkpoint = CheckpointRef {
+            parameters,
+            database,
+            held_out,
+            progress,
+        };
+        let serialized = serde_cbor::to_vec(&checkpoint).unwrap();
+        self.storage
+            .replace(&self.storage.checkpoint_path, &serialized)
+            .expect("Failed to write checkpoint to file.");
+    }
+}
+
+impl Checkpoint {
+    /// Loads the checkpoint from the checkpoint file configured in the [`Storage`].
+    pub(crate) fn load(storage: &Storage) -> Self {
+        let buffer = storage
+            .read(&storage.checkpoint_path)
+            .expect("Failed to read checkpoint from file.");
+        serde_cbor::from_slice(&buffer).unwrap()
+    }
+
+    /// Deletes the checkpoint file once training has finished.
+    pub(crate) fn remove(storage: &Storage) {
+        storage
+            .remove(&storage.checkpoint_path)
+            .expect("Failed to remove checkpoint file.");
+    }
+}
@@ -1,9 +1,800 @@
+use crate::als;
+use crate::bpr;
+use crate::checkpoint::Checkpointing;
+use crate::evaluation::Split;
+use crate::kernel::{Kernel, Precision, Scalar};
+use crate::knn;
+use crate::optimizer::{Moments, Optimizer};
+use crate::pagerank;
+use crate::popularity;
+use crate::recommender::{Model, ModelRef, Recommender};
+use crate::report::{Record, TrainingLog};
+use crate::sgd;
+use crate::storage::Storage;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Normal};
 use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
-use std::fs::File;
-use std::io::{Read, Write};
 use std::ops::AddAssign;
+use std::sync::Arc;
+
+/// The training algorithm of a factor model.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum Algorithm {
+    /// Funk's gradient descent, which learns the latent factors one after another.
+    Funk,
+
+    /// SVD++ after Koren, trained like Funk's model. Users are additionally described by the
+    /// implicit factors of all articles they voted on, regardless of the vote.
+    SvdPlusPlus,
+
+    /// Alternating least squares, which solves for all user factors and all article factors in
+    /// turn.
+    Als,
+
+    /// Alternating least squares on implicit feedback after Hu, Koren and Volinsky. Every pair of
+    /// user and article counts as observed, with a higher confidence for pairs with a vote.
+    ImplicitAls,
+
+    /// Stochastic gradient descent, which visits the votes in random order and learns all factors
+    /// at once.
+    Sgd,
+
+    /// Bayesian personalized ranking, which learns to rank upvoted articles above other articles
+    /// by stochastic gradient descent on sampled pairs.
+    Bpr,
+}
+
+impl Algorithm {
+    /// Whether the algorithm learns the factors one after another. Only these algorithms can save
+    /// checkpoints.
+    pub(crate) fn incremental(self) -> bool {
+        matches!(self, Algorithm::Funk | Algorithm::SvdPlusPlus)
+    }
+
+    /// Whether the algorithm is trained with gradient descent on the votes and therefore supports
+    /// all losses.
+    pub(crate) fn gradient_descent(self) -> bool {
+        matches!(
+            self,
+            Algorithm::Funk | Algorithm::SvdPlusPlus | Algorithm::Sgd
+        )
+    }
+
+    /// Whether the algorithm follows the gradients of its objective, so the steps can be adapted
+    /// by an optimizer.
+    pub(crate) fn gradient_based(self) -> bool {
+        self.gradient_descent() || self == Algorithm::Bpr
+    }
+}
+
+/// The kind of prediction model that is trained.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum ModelKind {
+    /// A factor model, trained with the configured algorithm.
+    #[default]
+    Factors,
+
+    /// Item-based k-nearest neighbors, which predicts a vote from the user's votes on the most
+    /// similar articles instead of learning factors.
+    ItemKnn,
+
+    /// User-based k-nearest neighbors, which predicts a vote from the votes of the most similar
+    /// users on the article.
+    UserKnn,
+
+    /// The damped mean vote of every article, the same for all users. A baseline for the other
+    /// models and a member of blends.
+    Popularity,
+
+    /// Personalized PageRank on the graph of upvotes, which ranks articles by how often a random
+    /// walk from the user's upvoted articles visits them. Its scores are not votes.
+    PageRank,
+}
+
+impl ModelKind {
+    /// Whether the model supports the similarity. Models without neighbors ignore it.
+    pub(crate) fn supports(self, similarity: Similarity) -> bool {
+        match self {
+            ModelKind::ItemKnn => similarity != Similarity::Pearson,
+            ModelKind::UserKnn => similarity != Similarity::AdjustedCosine,
+            ModelKind::Factors | ModelKind::Popularity | ModelKind::PageRank => true,
+        }
+    }
+}
+
+/// The loss minimized by the gradient descent algorithms.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum Loss {
+    /// The square error between the score and the vote of 1 or -1.
+    #[default]
+    Squared,
+
+    /// The logistic loss, which treats the sigmoid of the score as the probability of an upvote.
+    /// Votes are binary, so unlike the squared loss this gives predictions a meaning of their
+    /// own.
+    Logistic,
+}
+
+impl Loss {
+    /// Converts the score of a model trained with this loss into a predicted vote between -1 and
+    /// 1. With the logistic loss, this is the expected vote.
+    pub(crate) fn vote(self, score: f64) -> f64 {
+        match self {
+            Loss::Squared => score,
+            Loss::Logistic => (score / 2.0).tanh(),
+        }
+    }
+
+    /// Converts a predicted vote into the score that predicts it. The inverse of [`Loss::vote`].
+    pub(crate) fn score(self, vote: f64) -> f64 {
+        match self {
+            Loss::Squared => vote,
+            // votes of exactly 1 or -1 would need an infinite score
+            Loss::Logistic => 2.0 * vote.clamp(-0.999, 0.999).atanh(),
+        }
+    }
+
+    /// The negative derivative of the loss of a vote with respect to the score, i.e. the error
+    /// the gradients are scaled with.
+    pub(crate) fn error(self, vote: f64, score: f64) -> f64 {
+        match self {
+            Loss::Squared => vote - score,
+            Loss::Logistic => (vote + 1.0) / 2.0 - 1.0 / (1.0 + (-score).exp()),
+        }
+    }
+}
+
+/// How the factors are initialized before training.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) enum Initialization {
+    /// All factors start with the same value. Algorithms that learn all factors at once cannot
+    /// tell the factors apart then, so this is only useful for Funk's incremental training.
+    Constant { value: f64 },
+
+    /// Factors are drawn uniformly between `-scale` and `scale`.
+    Uniform { scale: f64 },
+
+    /// Factors are drawn from a normal distribution with mean 0 and standard deviation `scale`.
+    Normal { scale: f64 },
+}
+
+impl Initialization {
+    /// Creates a matrix of initial factors, drawing random values from `rng`.
+    pub(crate) fn matrix(
+        self,
+        rows: usize,
+        columns: usize,
+        rng: &mut StdRng,
+    ) -> nalgebra::DMatrix<f64> {
+        match self {
+            Initialization::Constant { value } => {
+                nalgebra::DMatrix::from_element(rows, columns, value)
+            }
+            Initialization::Uniform { scale } => {
+                nalgebra::DMatrix::from_fn(rows, columns, |_, _| rng.gen_range(-scale..=scale))
+            }
+            Initialization::Normal { scale } => {
+                let normal = Normal::new(0.0, scale).expect("Invalid initialization scale.");
+                nalgebra::DMatrix::from_fn(rows, columns, |_, _| normal.sample(rng))
+            }
+        }
+    }
+}
+
+/// How the regularization of a user or article grows with their number of votes. The trainers
+/// that learn from the votes one by one (or sum the gradient over them) penalize a parameter once
+/// per vote, which is the weighted-λ regularization of Zhou et al. It keeps heavy voters from
+/// overfitting, but it also penalizes them far more than their data warrants.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) enum RegularizationScaling {
+    /// The regularization is multiplied by the number of votes.
+    #[default]
+    WeightedLambda,
+
+    /// The regularization is the same for all users and articles, however many votes they have.
+    Constant,
+
+    /// The regularization is multiplied by the number of votes to the power of `exponent`, which
+    /// lies between the constant (0) and the weighted-λ regularization (1).
+    Frequency { exponent: f64 },
+}
+
+impl RegularizationScaling {
+    /// The factor the regularization of a user or article with `votes` votes is multiplied by.
+    pub(crate) fn scale(self, votes: f64) -> f64 {
+        match self {
+            RegularizationScaling::WeightedLambda => votes,
+            RegularizationScaling::Constant => 1.0,
+            RegularizationScaling::Frequency { exponent } => votes.powf(exponent),
+        }
+    }
+
+    /// The share of the regularization applied at each of `votes` votes by the trainers that
+    /// visit the votes one by one, so that all visits together add up to [`Self::scale`].
+    pub(crate) fn share(self, votes: usize) -> f64 {
+        if votes == 0 {
+            0.0
+        } else {
+            self.scale(votes as f64) / votes as f64
+        }
+    }
+}
+
+/// The L2 regularization strengths of the parameters of one side, users or articles.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Penalty {
+    /// The strength for the factors.
+    pub(crate) factors: f64,
+
+    /// The strength for the biases.
+    pub(crate) biases: f64,
+}
+
+/// How the propensity of an article is estimated for inverse propensity weighting. The propensity
+/// is the probability that a user sees the article and votes on it. Old classics are seen by far
+/// more users than the rest, so their votes dominate the training loss and the recommendations.
+/// Weighting every vote by the inverse propensity of its article evens this out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum Propensity {
+    /// All votes are weighted equally.
+    #[default]
+    Uniform,
+
+    /// From the number of votes of the article itself.
+    Popularity,
+
+    /// From the mean number of votes of articles of a similar age, ordered by their page ids.
+    Age,
+
+    /// From the mean number of votes of the articles in the same series. SCP series span a
+    /// thousand numbers each, so `scp-6000` to `scp-6999` form the seventh series.
+    Series,
+}
+
+impl Propensity {
+    /// The propensity the debiased metrics weight the held out votes of a model with: the one the
+    /// model was trained with, or the popularity of the articles if it was trained without
+    /// inverse propensity weighting.
+    pub(crate) fn debiasing(self) -> Self {
+        match self {
+            Propensity::Uniform => Propensity::Popularity,
+            propensity => propensity,
+        }
+    }
+}
+
+/// How the similarity of two articles or two users is computed by the neighborhood models.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum Similarity {
+    /// The cosine of the angle between the vote vectors, with upvotes as 1 and downvotes as -1.
+    /// Users are only compared on the articles both voted on.
+    #[default]
+    Cosine,
+
+    /// Cosine similarity of articles after subtracting each user's mean vote, so users who upvote
+    /// nearly everything do not make all articles look alike. Only for articles.
+    AdjustedCosine,
+
+    /// The Pearson correlation of the votes two users gave to the articles both voted on,
+    /// relative to each user's mean vote. Only for users.
+    Pearson,
+
+    /// The number of articles (users) upvoted by both users (articles) divided by the number
+    /// upvoted by either of them.
+    Jaccard,
+}
+
+/// Which articles BPR samples as the negative side of a pair, i.e. as an article that should be
+/// ranked below one the user upvoted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum NegativeSampling {
+    /// Any article the user did not upvote.
+    Any,
+
+    /// Only articles the user did not vote on.
+    Unvoted,
+
+    /// Only articles the user downvoted. Users without downvotes are paired with any article
+    /// they did not upvote.
+    Downvoted,
+}
+
+/// Hyperparameters of the training process. The defaults are close to those in the original Funk
+/// paper. Parameters missing from checkpoints written by older versions take their defaults.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct TrainingParameters {
+    /// The kind of prediction model.
+    pub(crate) model: ModelKind,
+
+    /// The training algorithm of factor models.
+    pub(crate) algorithm: Algorithm,
+
+    /// The number of latent factors of the model.
+    pub(crate) latent_factors: usize,
+
+    /// The number of gradient descent iterations per latent factor.
+    pub(crate) iterations: usize,
+
+    /// The step size of gradient descent.
+    pub(crate) learning_rate: f64,
+
+    /// How the gradient-based algorithms turn gradients into steps.
+    pub(crate) optimizer: Optimizer,
+
+    /// The floating point precision full-batch gradient descent computes the gradients in.
+    /// Ignored by the other algorithms.
+    #[serde(default = "Precision::legacy")]
+    pub(crate) precision: Precision,
+
+    /// The L2 regularization strength applied to the factors and biases.
+    pub(crate) regularization: f64,
+
+    /// The regularization strength of the user factors, if it differs from `regularization`.
+    pub(crate) user_regularization: Option<f64>,
+
+    /// The regularization strength of the article factors, if it differs from `regularization`.
+    pub(crate) article_regularization: Option<f64>,
+
+    /// The regularization strength of the biases, if it differs from the one of the factors of
+    /// the same side. Zero leaves the biases unregularized.
+    pub(crate) bias_regularization: Option<f64>,
+
+    /// How the regularization grows with the number of votes of a user or article. Ignored by
+    /// implicit ALS, which regularizes every user and article once, and by BPR, which samples its
+    /// pairs instead of visiting the votes.
+    pub(crate) regularization_scaling: RegularizationScaling,
+
+    /// Learn a global mean and per-user and per-article biases in addition to the factors.
+    pub(crate) biases: bool,
+
+    /// The loss minimized by the gradient descent algorithms.
+    pub(crate) loss: Loss,
+
+    /// How much more a vote is trusted than the absence of a vote by implicit ALS. Ignored by the
+    /// other algorithms.
+    pub(crate) confidence: f64,
+
+    /// Which articles BPR ranks below the upvoted ones. Ignored by the other algorithms.
+    pub(crate) negative_sampling: NegativeSampling,
+
+    /// How the factors are initialized. By default, the funk and svdpp algorithms start all
+    /// factors at 0.1 like the original Funk algorithm, and the others draw them uniformly between
+    /// -0.1 and 0.1, since they cannot tell identical factors apart.
+    pub(crate) initialization: Option<Initialization>,
+
+    /// How the votes are weighted by the inverse propensity of their article. Only supported by
+    /// the gradient descent algorithms.
+    pub(crate) propensity: Propensity,
+
+    /// How the neighborhood models compute the similarity of two articles or users. Ignored by
+    /// the other algorithms.
+    pub(crate) similarity: Similarity,
+
+    /// The number of most similar articles or users the neighborhood models keep per article or
+    /// user. Ignored by the other algorithms.
+    pub(crate) neighbors: usize,
+
+    /// The probability that the random walk of the PageRank model jumps back to its start at
+    /// every step. Ignored by the other models.
+    pub(crate) restart: f64,
+
+    /// The seed for the initialization and other random decisions during training.
+    pub(crate) seed: u64,
+
+    /// Make training reproducible: with the same seed, two runs produce bit-identical models,
+    /// independent of the number of threads. This disables the lock-free parallel updates of the
+    /// stochastic algorithms.
+    pub(crate) deterministic: bool,
+
+    /// Stop training when the error on the validation votes stops improving. Requires votes to be
+    /// held out for validation.
+    pub(crate) early_stopping: Option<EarlyStopping>,
+
+    /// Choose the number of latent factors on the validation votes: no further factors are
+    /// trained once the validation error has not improved for this many factors, and the model
+    /// keeps the factors up to the lowest error. Only supported by the algorithms that learn one
+    /// factor after another, and requires votes to be held out for validation.
+    pub(crate) rank_selection: Option<usize>,
+}
+
+/// Configuration of early stopping on the validation error.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub(crate) struct EarlyStopping {
+    /// The number of iterations without improvement of the validation error after which a factor
+    /// stops. When stopping the whole training, also the number of factors without improvement
+    /// after which no further factors are trained.
+    pub(crate) patience: usize,
+
+    /// Whether only the current factor or the whole training stops.
+    pub(crate) scope: StoppingScope,
+}
+
+/// What is stopped when the validation error stops improving. In both cases, each factor is
+/// stopped early and restored to its best values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum StoppingScope {
+    /// Training always continues with the next factor.
+    Factor,
+
+    /// No further factors are trained once the last factors did not improve the validation
+    /// error. All factors are restored to the values they had at the lowest validation error.
+    Training,
+}
+
+impl Default for TrainingParameters {
+    fn default() -> Self {
+        Self {
+            model: ModelKind::Factors,
+            algorithm: Algorithm::Funk,
+            latent_factors: 30,
+            iterations: 120,
+            learning_rate: 0.004,
+            optimizer: Optimizer::Plain,
+            precision: Precision::Single,
+            regularization: 0.02,
+            user_regularization: None,
+            article_regularization: None,
+            bias_regularization: None,
+            regularization_scaling: RegularizationScaling::WeightedLambda,
+            biases: false,
+            loss: Loss::Squared,
+            confidence: 40.0,
+            negative_sampling: NegativeSampling::Any,
+            initialization: None,
+            propensity: Propensity::Uniform,
+            similarity: Similarity::Cosine,
+            neighbors: 50,
+            restart: 0.3,
+            seed: 0,
+            deterministic: false,
+            early_stopping: None,
+            rank_selection: None,
+        }
+    }
+}
+
+impl TrainingParameters {
+    /// Whether a factor model is trained with gradient descent on the votes, which supports all
+    /// losses and weighting the votes.
+    pub(crate) fn gradient_descent(&self) -> bool {
+        self.model == ModelKind::Factors && self.algorithm.gradient_descent()
+    }
+
+    /// Whether a factor model is trained by following gradients, which supports the optimizers.
+    pub(crate) fn gradient_based(&self) -> bool {
+        self.model == ModelKind::Factors && self.algorithm.gradient_based()
+    }
+
+    /// Whether a factor model is trained one factor after another, which can save checkpoints.
+    pub(crate) fn incremental(&self) -> bool {
+        self.model == ModelKind::Factors && self.algorithm.incremental()
+    }
+
+    /// How the factors are initialized, resolving the default of the algorithm.
+    pub(crate) fn initialization(&self) -> Initialization {
+        match self.initialization {
+            Some(initialization) => initialization,
+            None if self.algorithm.incremental() => Initialization::Constant { value: 0.1 },
+            None => Initialization::Uniform { scale: 0.1 },
+        }
+    }
+
+    /// The regularization strengths of the user factors and biases.
+    pub(crate) fn user_penalty(&self) -> Penalty {
+        self.penalty(self.user_regularization)
+    }
+
+    /// The regularization strengths of the article factors and biases. They also apply to the
+    /// implicit factors of SVD++, which belong to the articles.
+    pub(crate) fn article_penalty(&self) -> Penalty {
+        self.penalty(self.article_regularization)
+    }
+
+    /// The regularization strengths of one side, given its override of the factor strength.
+    fn penalty(&self, factors: Option<f64>) -> Penalty {
+        let factors = factors.unwrap_or(self.regularization);
+        Penalty {
+            factors,
+            biases: self.bias_regularization.unwrap_or(factors),
+        }
+    }
+}
+
+/// The baseline predictors of a biased factor model, following Koren's formulation: a vote is
+/// predicted as `global_mean + user_bias + article_bias + user_factor · article_factor`. The biases
+/// capture that some users upvote nearly everything and some articles are upvoted by nearly
+/// everyone, so the factors only have to explain the remaining interaction.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct Biases {
+    /// The mean of all training votes.
+    pub(crate) global_mean: f64,
+
+    /// The bias of each user, indexed by user id.
+    pub(crate) user: nalgebra::DVector<f64>,
+
+    /// The bias of each article, indexed by article id.
+    pub(crate) article: nalgebra::DVector<f64>,
+}
+
+impl Biases {
+    /// Creates biases with the given global mean and all user and article biases set to zero.
+    pub(crate) fn new(global_mean: f64, users: usize, articles: usize) -> Self {
+        Self {
+            global_mean,
+            user: nalgebra::DVector::zeros(users),
+            article: nalgebra::DVector::zeros(articles),
+        }
+    }
+
+    /// The baseline prediction of a user's vote for an article.
+    pub(crate) fn predict(&self, user_id: usize, article_id: usize) -> f64 {
+        self.global_mean + self.user[user_id] + self.article[article_id]
+    }
+}
+
+/// The implicit feedback part of an SVD++ model. Every article has an implicit factor vector, and a
+/// user is described by their explicit factors plus the sum of the implicit factors of all
+/// articles they voted on, scaled by the inverse square root of their number of votes. The mere
+/// fact that a user voted on an article thereby tells something about them, whatever the vote.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct ImplicitFactors {
+    /// The implicit factors of each article, indexed by article id.
+    factors: nalgebra::DMatrix<f64>,
+
+    /// The scaled sum of implicit factors of each user, indexed by user id. It is derived from
+    /// `factors` and therefore not stored.
+    #[serde(skip, default = "empty_matrix")]
+    user_sums: nalgebra::DMatrix<f64>,
+}
+
+impl ImplicitFactors {
+    /// Creates implicit factors that are all zero.
+    fn new(users: usize, articles: usize, latent_factors: usize) -> Self {
+        Self {
+            factors: nalgebra::DMatrix::zeros(articles, latent_factors),
+            user_sums: nalgebra::DMatrix::zeros(users, latent_factors),
+        }
+    }
+
+    /// Recomputes the sums of all users from the implicit factors of the articles in
+    /// `user_votes`.
+    fn aggregate(&mut self, user_votes: &[Vec<usize>]) {
+        self.user_sums = nalgebra::DMatrix::zeros(user_votes.len(), self.factors.ncols());
+        for column in 0..self.factors.ncols() {
+            self.aggregate_column(user_votes, column);
+        }
+    }
+
+    /// Recomputes one column of the sums of all users from the implicit factors of the articles in
+    /// `user_votes`.
+    fn aggregate_column(&mut self, user_votes: &[Vec<usize>], column: usize) {
+        for (user_id, articles) in user_votes.iter().enumerate() {
+            if articles.is_empty() {
+                continue;
+            }
+            let sum: f64 = articles
+                .iter()
+                .map(|&article_id| self.factors[(article_id, column)])
+                .sum();
+            self.user_sums[(user_id, column)] = sum / (articles.len() as f64).sqrt();
+        }
+    }
+}
+
+/// An empty matrix, used as placeholder for matrices that are not stored.
+fn empty_matrix() -> nalgebra::DMatrix<f64> {
+    nalgebra::DMatrix::zeros(0, 0)
+}
+
+/// The lowest estimated propensity, which bounds the weight of the votes of rarely seen articles.
+const MIN_PROPENSITY: f64 = 0.05;
+
+/// The exponent of the relative vote count in the estimated propensity. Vote counts grow faster
+/// than the number of users who saw an article, since popular articles also attract more votes
+/// from those who saw them, so the propensity grows slower than the vote count.
+const PROPENSITY_EXPONENT: f64 = 0.5;
+
+/// The number of groups of articles of a similar age for [`Propensity::Age`].
+const AGE_GROUPS: usize = 10;
+
+/// The parameters of a factor model during training.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct FactorState {
+    user_factors: nalgebra::DMatrix<f64>,
+    article_factors: nalgebra::DMatrix<f64>,
+    implicit_factors: Option<ImplicitFactors>,
+    biases: Biases,
+}
+
+impl FactorState {
+    /// Predicts the vote of a user for an article.
+    fn predict(&self, user_id: usize, article_id: usize) -> f64 {
+        self.predict_rank(user_id, article_id, self.article_factors.ncols())
+    }
+
+    /// Predicts the vote of a user for an article with only the first `rank` factors, as if the
+    /// model had no further factors.
+    fn predict_rank(&self, user_id: usize, article_id: usize, rank: usize) -> f64 {
+        let article_factor = self.article_factors.view((article_id, 0), (1, rank));
+        let mut prediction = self.biases.predict(user_id, article_id)
+            + self
+                .user_factors
+                .view((user_id, 0), (1, rank))
+                .dot(&article_factor);
+        if let Some(implicit) = &self.implicit_factors {
+            prediction += implicit
+                .user_sums
+                .view((user_id, 0), (1, rank))
+                .dot(&article_factor);
+        }
+        prediction
+    }
+
+    /// Drops all factors after the first `rank`.
+    fn truncate(&mut self, rank: usize) {
+        self.user_factors = self.user_factors.columns(0, rank).into_owned();
+        self.article_factors = self.article_factors.columns(0, rank).into_owned();
+        if let Some(implicit) = &mut self.implicit_factors {
+            implicit.factors = implicit.factors.columns(0, rank).into_owned();
+            implicit.user_sums = implicit.user_sums.columns(0, rank).into_owned();
+        }
+    }
+
+    /// The factors of all users, including the implicit sums of SVD++.
+    fn user_values(&self) -> nalgebra::DMatrix<f64> {
+        match &self.implicit_factors {
+            Some(implicit) => &self.user_factors + &implicit.user_sums,
+            None => self.user_factors.clone(),
+        }
+    }
+
+    /// One factor of all users, including the implicit sums of SVD++.
+    fn user_value_column(&self, factor: usize) -> nalgebra::DVector<f64> {
+        let mut column = self.user_factors.column(factor).into_owned();
+        if let Some(implicit) = &self.implicit_factors {
+            column += implicit.user_sums.column(factor);
+        }
+        column
+    }
+}
+
+/// The running averages of the optimizer for all parameters of full-batch gradient descent. Only
+/// one factor is trained at a time, so the averages of the factors cover a single column.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct FactorMoments {
+    user: Moments,
+    article: Moments,
+    implicit: Moments,
+    user_bias: Moments,
+    article_bias: Moments,
+}
+
+impl FactorMoments {
+    /// Creates the averages for the parameters of `state`.
+    fn new(state: &FactorState, optimizer: Optimizer) -> Self {
+        let (users, articles) = (state.user_factors.nrows(), state.article_factors.nrows());
+        Self {
+            user: Moments::new(optimizer, users),
+            article: Moments::new(optimizer, articles),
+            implicit: Moments::new(optimizer, articles),
+            user_bias: Moments::new(optimizer, users),
+            article_bias: Moments::new(optimizer, articles),
+        }
+    }
+
+    /// Whether the averages cover the parameters of `state` updated by `optimizer`.
+    fn fits(&self, state: &FactorState, optimizer: Optimizer) -> bool {
+        let (users, articles) = (state.user_factors.nrows(), state.article_factors.nrows());
+        self.user.fits(optimizer, users)
+            && self.article.fits(optimizer, articles)
+            && self.implicit.fits(optimizer, articles)
+            && self.user_bias.fits(optimizer, users)
+            && self.article_bias.fits(optimizer, articles)
+    }
+
+    /// Forgets the gradients of the factors when the next factor is trained. The biases are
+    /// trained along with every factor, so their averages are kept.
+    fn start_factor(&mut self) {
+        self.user.reset();
+        self.article.reset();
+        self.implicit.reset();
+    }
+}
+
+/// How far full-batch gradient descent has come. It is saved in checkpoints, so training can be
+/// resumed where it stopped.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct Progress {
+    /// The current parameters of the model.
+    state: FactorState,
+
+    /// The running averages of the optimizer for the current parameters. Checkpoints written
+    /// before the optimizers existed have none, which the plain optimizer does not need.
+    #[serde(default)]
+    moments: FactorMoments,
+
+    /// The factor currently being trained.
+    factor: usize,
+
+    /// The number of iterations done on the current factor.
+    iteration: usize,
+
+    /// The training error after the last iteration.
+    mean_square_error: f64,
+
+    /// The lowest validation error during the current factor together with the state achieving
+    /// it.
+    factor_validation_error: f64,
+    best_factor_state: Option<FactorState>,
+    iterations_without_improvement: usize,
+
+    /// The lowest validation error after any factor together with the state achieving it.
+    best_validation_error: f64,
+    best_state: Option<FactorState>,
+    factors_without_improvement: usize,
+
+    /// The validation error after each factor, if the number of factors is chosen on the
+    /// validation votes.
+    #[serde(default)]
+    rank_errors: Vec<f64>,
+}
+
+impl Progress {
+    /// Creates the progress of a training that starts with the given parameters.
+    fn new(state: FactorState, optimizer: Optimizer) -> Self {
+        Self {
+            moments: FactorMoments::new(&state, optimizer),
+            state,
+            factor: 0,
+            iteration: 0,
+            mean_square_error: 0.0,
+            factor_validation_error: f64::INFINITY,
+            best_factor_state: None,
+            iterations_without_improvement: 0,
+            best_validation_error: f64::INFINITY,
+            best_state: None,
+            factors_without_improvement: 0,
+            rank_errors: Vec::new(),
+        }
+    }
+
+    /// The factor currently being trained, counting from 0.
+    pub(crate) fn factor(&self) -> usize {
+        self.factor
+    }
+
+    /// The number of iterations done on the current factor.
+    pub(crate) fn iteration(&self) -> usize {
+        self.iteration
+    }
+
+    /// Moves on to training the given factor.
+    fn start_factor(&mut self, factor: usize) {
+        self.factor = factor;
+        self.iteration = 0;
+        self.moments.start_factor();
+        self.factor_validation_error = f64::INFINITY;
+        self.best_factor_state = None;
+        self.iterations_without_improvement = 0;
+    }
+
+    /// Recomputes the implicit sums of all states, which are not saved.
+    fn aggregate(&mut self, user_votes: &[Vec<usize>]) {
+        let states = [&mut self.best_factor_state, &mut self.best_state]
+            .into_iter()
+            .flatten()
+            .chain(std::iter::once(&mut self.state));
+        for state in states {
+            if let Some(implicit) = &mut state.implicit_factors {
+                implicit.aggregate(user_votes);
+            }
+        }
+    }
+}
 
 /// Database of articles and user votes. This struct can be serialized to store it.
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -38,19 +829,18 @@ impl Database {
     }
 
     /// Loads the database from file.
-    pub(crate) fn load() -> Self {
-        let mut file = File::open("database.bin").expect("Failed to open database file.");
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer)
+    pub(crate) fn load(storage: &Storage) -> Self {
+        let buffer = storage
+            .read(&storage.database_path)
             .expect("Failed to read database from file.");
         serde_cbor::from_slice(&buffer).unwrap()
     }
 
-    /// Saves the database to file.
-    pub(crate) fn save(&self) {
-        let mut file = File::create("database.bin").unwrap();
+    /// Saves the database to file. The previous database file is kept as a backup.
+    pub(crate) fn save(&self, storage: &Storage) {
         let serialized = serde_cbor::to_vec(self).unwrap();
-        file.write_all(&serialized)
+        storage
+            .write(&storage.database_path, &serialized)
             .expect("Failed to write database to file.");
     }
 
@@ -59,7 +849,7 @@ impl Database {
     /// - ```article```: The article name.
     /// - ```page_id```: The wikidot page id of the article for future requests
     /// - ```votes```: A list of tuples of user ids and votes. The first component of the tuple is
-    /// the user id, the second component is the vote (true for upvote, false for downvote).
+    ///   the user id, the second component is the vote (true for upvote, false for downvote).
     pub(crate) fn add_article(
         &mut self,
         article: String,
@@ -90,126 +880,617 @@ impl Database {
         user_id
     }
 
-    /// Use linear regression to estimate a singular value decomposition of the user-vote matrix.
-    /// The result is a prediction model that can be used to predict the votes of users for articles
-    /// they have not yet voted on.
+    /// Trains a prediction model of the kind configured in `parameters`. The result is a
+    /// prediction model that can be used to predict the votes of users for articles they have not
+    /// yet voted on. The `held_out` votes are not used for training, but stored in the model for
+    /// later evaluation. If early stopping is configured, the validation votes of `held_out`
+    /// decide when to stop. Training progress is reported to the `log`.
     pub(crate) fn train_prediction_model(
         self,
-        latent_factors: usize,
-        iterations: usize,
-        learning_rate: f64,
-        regularization: f64,
-    ) {
-        let mut user_factors =
-            nalgebra::DMatrix::from_fn(self.users.len(), latent_factors, |_, _| 0.1);
-        let mut article_factors =
-            nalgebra::DMatrix::from_fn(self.articles.len(), latent_factors, |_, _| 0.1);
+        parameters: &TrainingParameters,
+        held_out: Split,
+        log: &TrainingLog,
+    ) -> Model {
+        match parameters.model {
+            ModelKind::Factors => {
+                Model::Factors(self.train_factor_model(parameters, held_out, log))
+            }
+            ModelKind::ItemKnn => {
+                Model::Neighborhood(knn::train_items(self, parameters, held_out, log))
+            }
+            ModelKind::UserKnn => {
+                Model::Neighborhood(knn::train_users(self, parameters, held_out, log))
+            }
+            ModelKind::Popularity => Model::Popularity(popularity::train(self, held_out, log)),
+            ModelKind::PageRank => {
+                Model::PageRank(pagerank::train(self, parameters, held_out, log))
+            }
+        }
+    }
+
+    /// Trains a factor model with the algorithm configured in `parameters`, like
+    /// [`Database::train_prediction_model`].
+    pub(crate) fn train_factor_model(
+        self,
+        parameters: &TrainingParameters,
+        held_out: Split,
+        log: &TrainingLog,
+    ) -> FactorModel {
+        match parameters.algorithm {
+            Algorithm::Funk | Algorithm::SvdPlusPlus => {
+                self.train_gradient_descent(parameters, held_out, log, None, None)
+            }
+            Algorithm::Als => als::train(self, parameters, held_out, log),
+            Algorithm::ImplicitAls => als::train_implicit(self, parameters, held_out, log),
+            Algorithm::Sgd => sgd::train(self, parameters, held_out, log),
+            Algorithm::Bpr => bpr::train(self, parameters, held_out, log),
+        }
+    }
+
+    /// Use linear regression to estimate a singular value decomposition of the user-vote matrix.
+    /// The factors are learned one after another by full-batch gradient descent. Training starts
+    /// from `progress` if given, which must have been made with the same parameters on this
+    /// database. With `checkpointing`, the progress is saved regularly. Only the funk and svdpp
+    /// algorithms are trained this way.
+    pub(crate) fn train_gradient_descent(
+        self,
+        parameters: &TrainingParameters,
+        held_out: Split,
+        log: &TrainingLog,
+        progress: Option<Progress>,
+        checkpointing: Option<&Checkpointing>,
+    ) -> FactorModel {
+        match parameters.precision {
+            Precision::Single => {
+                self.descend::<f32>(parameters, held_out, log, progress, checkpointing)
+            }
+            Precision::Double => {
+                self.descend::<f64>(parameters, held_out, log, progress, checkpointing)
+            }
+        }
+    }
+
+    /// Trains like [`Database::train_gradient_descent`], computing the gradients in the precision
+    /// `T`.
+    fn descend<T: Scalar>(
+        self,
+        parameters: &TrainingParameters,
+        held_out: Split,
+        log: &TrainingLog,
+        progress: Option<Progress>,
+        checkpointing: Option<&Checkpointing>,
+    ) -> FactorModel {
+        let TrainingParameters {
+            algorithm,
+            latent_factors,
+            iterations,
+            learning_rate,
+            optimizer,
+            regularization_scaling,
+            biases: learn_biases,
+            loss,
+            propensity,
+            seed,
+            early_stopping,
+            ..
+        } = *parameters;
+        let initialization = parameters.initialization();
+        let (user_penalty, article_penalty) =
+            (parameters.user_penalty(), parameters.article_penalty());
+
+        let user_votes = self.user_votes();
+        let weights = self.propensity_weights(propensity);
+        let mut rng = StdRng::seed_from_u64(seed);
 
-        for factor in 0..latent_factors {
-            println!("Factor {}/{}", factor + 1, latent_factors);
+        // without learned biases, they stay zero and do not affect the predictions
+        let global_mean = if learn_biases {
+            loss.score(self.mean_vote().unwrap_or(0.0))
+        } else {
+            0.0
+        };
+        let mut progress = match progress {
+            Some(mut progress) => {
+                progress.aggregate(&user_votes);
+                // checkpoints written before the optimizers were added have no averages, so the
+                // optimizer starts over with the current factor
+                if !progress.moments.fits(&progress.state, optimizer) {
+                    progress.moments = FactorMoments::new(&progress.state, optimizer);
+                }
+                progress
+            }
+            None => Progress::new(
+                FactorState {
+                    user_factors: initialization.matrix(self.users.len(), latent_factors, &mut rng),
+                    article_factors: initialization.matrix(
+                        self.articles.len(),
+                        latent_factors,
+                        &mut rng,
+                    ),
+                    implicit_factors: (algorithm == Algorithm::SvdPlusPlus).then(|| {
+                        ImplicitFactors::new(self.users.len(), self.articles.len(), latent_factors)
+                    }),
+                    biases: Biases::new(global_mean, self.users.len(), self.articles.len()),
+                },
+                optimizer,
+            ),
+        };
+        let mut last_checkpoint = std::time::Instant::now();
+        let mut kernel = Kernel::<T>::new(&self.article_votes, self.users.len());
+
+        // The gradients are summed over all votes, so with weighted-λ regularization every vote
+        // also contributes one regularization term to the gradient of its user's and article's
+        // parameters. The other scalings weigh these terms by a different function of the count.
+        let user_vote_counts = nalgebra::DVector::<f64>::from_iterator(
+            self.users.len(),
+            user_votes.iter().map(|votes| votes.len() as f64),
+        );
+        let article_vote_counts = nalgebra::DVector::<f64>::from_iterator(
+            self.articles.len(),
+            self.article_votes.iter().map(|votes| votes.len() as f64),
+        );
+        let user_scales = user_vote_counts.map(|count| regularization_scaling.scale(count));
+        let article_scales = article_vote_counts.map(|count| regularization_scaling.scale(count));
+
+        // Bias gradients are averaged over the votes of each user and article instead of summed.
+        // A bias is a single parameter shared by all votes of its user, so the summed gradient of
+        // a heavy voter would make the full-batch steps diverge.
+        let user_normalization = user_vote_counts.map(|count| count.max(1.0));
+        let article_normalization = article_vote_counts.map(|count| count.max(1.0));
+
+        for factor in progress.factor..latent_factors {
+            if factor != progress.factor {
+                progress.start_factor(factor);
+            }
+            kernel.start_factor(
+                factor,
+                &progress.state.user_values(),
+                &progress.state.article_factors,
+                &progress.state.biases,
+            );
+            if log.verbose {
+                println!("Factor {}/{}", factor + 1, latent_factors);
+            }
 
             let now = std::time::Instant::now();
-            let mut mean_square_error = 0.0;
-            for _ in 0..iterations {
-                let gradients = self
-                    .article_votes
-                    .par_iter()
-                    .enumerate()
-                    .fold(
-                        || {
-                            (
-                                nalgebra::DVector::<f64>::zeros(self.users.len()),
-                                nalgebra::DVector::<f64>::zeros(self.articles.len()),
-                                0.0,
-                                0,
-                            )
-                        },
-                        |(
-                            mut user_gradient,
-                            mut article_gradient,
-                            mut mean_square_error,
-                            mut count,
-                        ),
-                         (article_id, votes)| {
-                            for &(user_id, vote) in votes {
-                                let vote = if vote { 1.0 } else { -1.0 };
-
-                                let user_factor = user_factors.row(user_id);
-                                let article_factor = article_factors.row(article_id);
-                                let prediction = user_factor.dot(&article_factor);
-                                let error = vote - prediction;
-                                mean_square_error += error * error;
-
-                                let user_factor_value = user_factor[factor];
-                                let article_factor_value = article_factor[factor];
-                                user_gradient[user_id].add_assign(
-                                    article_factor_value * error
-                                        - user_factor_value * regularization,
-                                );
-                                article_gradient[article_id].add_assign(
-                                    user_factor_value * error
-                                        - article_factor_value * regularization,
-                                );
-                            }
-                            count += votes.len();
-                            (user_gradient, article_gradient, mean_square_error, count)
-                        },
-                    )
-                    .reduce(
-                        || {
-                            (
-                                nalgebra::DVector::<f64>::zeros(self.users.len()),
-                                nalgebra::DVector::<f64>::zeros(self.articles.len()),
-                                0.0,
-                                0,
-                            )
-                        },
-                        |a, b| (a.0 + b.0, a.1 + b.1, a.2 + b.2, a.3 + b.3),
+            while progress.iteration < iterations {
+                let iteration_start = std::time::Instant::now();
+                let square_error = kernel.compute_gradients(&weights, loss);
+                let mut user_gradient = kernel.user_gradient();
+                let mut article_gradient = kernel.article_gradient();
+                let user_bias_gradient = kernel.user_bias_gradient();
+                let article_bias_gradient = kernel.article_bias_gradient();
+
+                if let Some(implicit) = &mut progress.state.implicit_factors {
+                    // The implicit factor of an article influences the predictions of all users
+                    // who voted on it, scaled like in the user's implicit sum. Since the implicit
+                    // factors of articles with common voters are strongly coupled, the gradient
+                    // is averaged over the article's votes like the bias gradients, otherwise
+                    // the full-batch steps diverge.
+                    let implicit_gradient = self
+                        .article_votes
+                        .par_iter()
+                        .map(|votes| {
+                            votes
+                                .iter()
+                                .map(|&(user_id, _)| {
+                                    user_gradient[user_id]
+                                        / (user_votes[user_id].len() as f64).sqrt()
+                                })
+                                .sum()
+                        })
+                        .collect::<Vec<f64>>();
+                    let mut implicit_gradient = nalgebra::DVector::from_vec(implicit_gradient);
+                    implicit_gradient -= article_penalty.factors
+                        * article_scales.component_mul(&implicit.factors.column(factor));
+                    implicit_gradient.component_div_assign(&article_normalization);
+                    progress.moments.implicit.update(
+                        optimizer,
+                        learning_rate,
+                        &mut implicit_gradient,
                     );
+                    implicit
+                        .factors
+                        .column_mut(factor)
+                        .add_assign(implicit_gradient);
+                    implicit.aggregate_column(&user_votes, factor);
+                }
 
-                user_factors
+                user_gradient -= user_penalty.factors
+                    * user_scales.component_mul(&progress.state.user_factors.column(factor));
+                article_gradient -= article_penalty.factors
+                    * article_scales.component_mul(&progress.state.article_factors.column(factor));
+                let gradient_norm =
+                    (user_gradient.norm_squared() + article_gradient.norm_squared()).sqrt();
+                let moments = &mut progress.moments;
+                moments
+                    .user
+                    .update(optimizer, learning_rate, &mut user_gradient);
+                moments
+                    .article
+                    .update(optimizer, learning_rate, &mut article_gradient);
+                progress
+                    .state
+                    .user_factors
                     .column_mut(factor)
-                    .add_assign(learning_rate * gradients.0);
-                article_factors
+                    .add_assign(user_gradient);
+                progress
+                    .state
+                    .article_factors
                     .column_mut(factor)
-                    .add_assign(learning_rate * gradients.1);
-                mean_square_error = gradients.2 / gradients.3 as f64;
+                    .add_assign(article_gradient);
+
+                if learn_biases {
+                    let (biases, moments) = (&mut progress.state.biases, &mut progress.moments);
+                    let mut user_bias_gradient = (user_bias_gradient
+                        - user_penalty.biases * user_scales.component_mul(&biases.user))
+                    .component_div(&user_normalization);
+                    let mut article_bias_gradient = (article_bias_gradient
+                        - article_penalty.biases * article_scales.component_mul(&biases.article))
+                    .component_div(&article_normalization);
+                    moments
+                        .user_bias
+                        .update(optimizer, learning_rate, &mut user_bias_gradient);
+                    moments.article_bias.update(
+                        optimizer,
+                        learning_rate,
+                        &mut article_bias_gradient,
+                    );
+                    biases.user.add_assign(user_bias_gradient);
+                    biases.article.add_assign(article_bias_gradient);
+                    kernel.set_biases(biases);
+                }
+                kernel.set_column(
+                    &progress.state.user_value_column(factor),
+                    &progress.state.article_factors.column(factor).into_owned(),
+                );
+                progress.mean_square_error = square_error / self.total_votes as f64;
+
+                let validation_error = (early_stopping.is_some()
+                    || log.is_recording() && held_out.has_validation())
+                .then(|| {
+                    Self::mean_square_error(&held_out.validation, |user_id, article_id| {
+                        loss.vote(progress.state.predict(user_id, article_id))
+                    })
+                })
+                .flatten();
+                log.record(Record {
+                    factor: Some(factor),
+                    iteration: progress.iteration,
+                    training_loss: progress.mean_square_error,
+                    validation_loss: validation_error,
+                    gradient_norm: Some(gradient_norm),
+                    duration: iteration_start.elapsed(),
+                });
+                progress.iteration += 1;
+
+                if let (Some(EarlyStopping { patience, .. }), Some(validation_error)) =
+                    (early_stopping, validation_error)
+                {
+                    if validation_error < progress.factor_validation_error {
+                        progress.factor_validation_error = validation_error;
+                        progress.best_factor_state = Some(progress.state.clone());
+                        progress.iterations_without_improvement = 0;
+                    } else {
+                        progress.iterations_without_improvement += 1;
+                        if progress.iterations_without_improvement >= patience {
+                            if log.verbose {
+                                println!("Validation error stopped improving, stopping factor.");
+                            }
+                            break;
+                        }
+                    }
+                }
+
+                if let Some(checkpointing) = checkpointing {
+                    if last_checkpoint.elapsed() >= checkpointing.interval {
+                        checkpointing.save(parameters, &self, &held_out, &progress);
+                        last_checkpoint = std::time::Instant::now();
+                        if log.verbose {
+                            println!("Saved checkpoint.");
+                        }
+                    }
+                }
             }
 
-            println!("Factor finished in {}ms.", now.elapsed().as_millis());
-            println!("Mean square error: {}", mean_square_error);
-        }
+            if log.verbose {
+                println!("Factor finished in {}ms.", now.elapsed().as_millis());
+                println!("Mean square error: {}", progress.mean_square_error);
+            }
 
-        println!("Training finished.");
+            if let Some(EarlyStopping { patience, scope }) = early_stopping {
+                // restore the best values of this factor, since the last iterations have overfitted
+                if let Some(factor_state) = progress.best_factor_state.take() {
+                    progress.state = factor_state;
+                }
+                if log.verbose {
+                    println!(
+                        "Validation mean square error: {}",
+                        progress.factor_validation_error
+                    );
+                }
 
-        println!("Constructing read-filter...");
+                if scope == StoppingScope::Training {
+                    if progress.factor_validation_error < progress.best_validation_error {
+                        progress.best_validation_error = progress.factor_validation_error;
+                        progress.best_state = Some(progress.state.clone());
+                        progress.factors_without_improvement = 0;
+                    } else {
+                        progress.factors_without_improvement += 1;
+                        if progress.factors_without_improvement >= patience {
+                            if log.verbose {
+                                println!("Validation error stopped improving, stopping training.");
+                            }
+                            break;
+                        }
+                    }
+                }
+            }
 
-        // Construct a user-to-article filter to remove predictions about articles the user
-        // has already voted on.
-        let mut user_votes = vec![Vec::new(); self.users.len()];
-        for user in self.users.keys() {
-            let user_id = self.users[user];
-            let user_votes = &mut user_votes[user_id];
+            let Some(patience) = parameters.rank_selection else {
+                continue;
+            };
 
-            for (article_id, votes) in self.article_votes.iter().enumerate() {
-                if votes.iter().any(|(id, _)| *id == user_id) {
-                    user_votes.push(article_id);
+            // the validation error of the model without the factors that are not trained yet
+            let rank = factor + 1;
+            let Some(rank_error) =
+                Self::mean_square_error(&held_out.validation, |user_id, article_id| {
+                    loss.vote(progress.state.predict_rank(user_id, article_id, rank))
+                })
+            else {
+                continue;
+            };
+            progress.rank_errors.push(rank_error);
+            if log.verbose {
+                println!(
+                    "Validation mean square error with {} factors: {}",
+                    rank, rank_error
+                );
+            }
+            if rank_error < progress.best_validation_error {
+                progress.best_validation_error = rank_error;
+                progress.best_state = Some(progress.state.clone());
+                progress.factors_without_improvement = 0;
+            } else {
+                progress.factors_without_improvement += 1;
+                if progress.factors_without_improvement >= patience {
+                    if log.verbose {
+                        println!("Validation error stopped improving, stopping training.");
+                    }
+                    break;
                 }
             }
         }
 
-        let model = PredictionModel {
-            database: self,
-            user_factors,
-            article_factors,
+        // restore all factors to the state with the lowest validation error and drop the factors
+        // trained after it
+        let mut state = progress.best_state.unwrap_or(progress.state);
+        let rank_selection =
+            (!progress.rank_errors.is_empty()).then(|| RankSelection::new(progress.rank_errors));
+        if let Some(rank_selection) = &rank_selection {
+            state.truncate(rank_selection.rank);
+        }
+
+        if log.verbose {
+            println!("Training finished.");
+        }
+
+        FactorModel {
+            database: Arc::new(self),
+            user_factors: state.user_factors,
+            article_factors: state.article_factors,
+            biases: learn_biases.then_some(state.biases),
+            implicit_factors: state.implicit_factors,
+            loss,
             user_votes,
+            held_out,
+            rank_selection,
+        }
+    }
+
+    /// Lists the ids of all articles each user has voted on, indexed by user id. Articles are
+    /// visited in order, so each list is sorted.
+    pub(crate) fn user_votes(&self) -> Vec<Vec<usize>> {
+        let mut user_votes = vec![Vec::new(); self.users.len()];
+        for (article_id, votes) in self.article_votes.iter().enumerate() {
+            for &(user_id, _) in votes {
+                user_votes[user_id].push(article_id);
+            }
+        }
+        user_votes
+    }
+
+    /// Lists all votes of each user, indexed by user id. Each entry is a tuple of the article id
+    /// and the vote.
+    pub(crate) fn votes_by_user(&self) -> Vec<Vec<(usize, bool)>> {
+        let mut user_votes = vec![Vec::new(); self.users.len()];
+        for (article_id, votes) in self.article_votes.iter().enumerate() {
+            for &(user_id, vote) in votes {
+                user_votes[user_id].push((article_id, vote));
+            }
+        }
+        user_votes
+    }
+
+    /// The mean of all votes, counting upvotes as 1 and downvotes as -1, or `None` if there are
+    /// no votes.
+    pub(crate) fn mean_vote(&self) -> Option<f64> {
+        if self.total_votes == 0 {
+            return None;
+        }
+        let upvotes = self
+            .article_votes
+            .iter()
+            .flatten()
+            .filter(|(_, vote)| *vote)
+            .count();
+        Some((2 * upvotes) as f64 / self.total_votes as f64 - 1.0)
+    }
+
+    /// Estimates the propensity of every article, indexed by article id. Articles are grouped
+    /// according to `propensity`, and the propensity grows with the mean vote count of the
+    /// article's group relative to the most voted group.
+    pub(crate) fn propensities(&self, propensity: Propensity) -> Vec<f64> {
+        let articles = self.article_votes.len();
+        let groups = match propensity {
+            Propensity::Uniform => return vec![1.0; articles],
+            Propensity::Popularity => (0..articles).collect::<Vec<_>>(),
+            Propensity::Age => {
+                // page ids grow with the creation of the page
+                let mut order = (0..articles).collect::<Vec<_>>();
+                order.sort_by_key(|&article_id| {
+                    let page_id = self.page_ids[article_id].parse::<u64>();
+                    (page_id.unwrap_or(u64::MAX), article_id)
+                });
+                let mut groups = vec![0; articles];
+                for (rank, &article_id) in order.iter().enumerate() {
+                    groups[article_id] = rank * AGE_GROUPS / articles;
+                }
+                groups
+            }
+            Propensity::Series => {
+                // articles that are not numbered SCPs form a group of their own
+                let mut groups = vec![0; articles];
+                for (name, &article_id) in &self.articles {
+                    groups[article_id] = name
+                        .strip_prefix("scp-")
+                        .and_then(|number| number.parse::<usize>().ok())
+                        .map_or(0, |number| number / 1000 + 1);
+                }
+                groups
+            }
         };
 
-        let mut file = File::create("prediction_model.bin").unwrap();
-        let serialized = serde_cbor::to_vec(&model).unwrap();
-        file.write_all(&serialized)
-            .expect("Failed to write prediction model to file.");
-        println!("Saved prediction model to file.");
+        let mut sums = vec![(0.0, 0); groups.iter().max().map_or(0, |group| group + 1)];
+        for (&group, votes) in groups.iter().zip(&self.article_votes) {
+            sums[group].0 += votes.len() as f64;
+            sums[group].1 += 1;
+        }
+        let means = sums
+            .iter()
+            .map(|&(sum, count)| if count > 0 { sum / count as f64 } else { 0.0 })
+            .collect::<Vec<_>>();
+        let highest = means.iter().copied().fold(0.0, f64::max);
+        if highest == 0.0 {
+            return vec![1.0; articles];
+        }
+
+        groups
+            .iter()
+            .map(|&group| {
+                (means[group] / highest)
+                    .powf(PROPENSITY_EXPONENT)
+                    .max(MIN_PROPENSITY)
+            })
+            .collect()
+    }
+
+    /// The weight of the votes of every article for inverse propensity weighting, indexed by
+    /// article id. The inverse propensities are scaled so that the mean weight of all votes is 1,
+    /// which keeps the scale of the loss and therefore the learning rate.
+    pub(crate) fn propensity_weights(&self, propensity: Propensity) -> Vec<f64> {
+        let mut weights = self
+            .propensities(propensity)
+            .iter()
+            .map(|propensity| 1.0 / propensity)
+            .collect::<Vec<_>>();
+        let total = weights
+            .iter()
+            .zip(&self.article_votes)
+            .map(|(weight, votes)| weight * votes.len() as f64)
+            .sum::<f64>();
+        if total > 0.0 {
+            let scale = self.total_votes as f64 / total;
+            weights.iter_mut().for_each(|weight| *weight *= scale);
+        }
+        weights
+    }
+
+    /// Computes the mean square error of the predictions on the given votes, or `None` if there
+    /// are no votes. The errors are summed up in order, so the result does not depend on the
+    /// number of threads.
+    pub(crate) fn mean_square_error(
+        votes: &[Vec<(usize, bool)>],
+        predict: impl Fn(usize, usize) -> f64 + Sync,
+    ) -> Option<f64> {
+        let (square_error, count) = votes
+            .par_iter()
+            .enumerate()
+            .map(|(article_id, votes)| {
+                let square_error = votes
+                    .iter()
+                    .map(|&(user_id, vote)| {
+                        let vote = if vote { 1.0 } else { -1.0 };
+                        let error = vote - predict(user_id, article_id);
+                        error * error
+                    })
+                    .sum::<f64>();
+                (square_error, votes.len())
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .fold((0.0, 0), |a, b| (a.0 + b.0, a.1 + b.1));
+        (count > 0).then(|| square_error / count as f64)
+    }
+
+    /// Returns a copy of this database in which the votes of all articles are replaced by
+    /// `article_votes`. Users and articles are kept, so their ids stay valid.
+    pub(crate) fn with_votes(&self, article_votes: Vec<Vec<(usize, bool)>>) -> Self {
+        Self {
+            articles: self.articles.clone(),
+            page_ids: self.page_ids.clone(),
+            total_votes: article_votes.iter().map(Vec::len).sum(),
+            article_votes,
+            users: self.users.clone(),
+        }
+    }
+
+    /// The votes of all articles, indexed by article id. Each entry is a tuple of the user id and
+    /// the vote.
+    pub(crate) fn article_votes(&self) -> &[Vec<(usize, bool)>] {
+        &self.article_votes
+    }
+
+    /// The total number of votes in the database.
+    pub(crate) fn total_votes(&self) -> usize {
+        self.total_votes
+    }
+
+    /// The number of users in the database.
+    pub(crate) fn user_count(&self) -> usize {
+        self.users.len()
+    }
+
+    /// The number of articles in the database.
+    pub(crate) fn article_count(&self) -> usize {
+        self.articles.len()
+    }
+
+    /// The id of the user with the given name, if the user is in the database.
+    pub(crate) fn user_id(&self, name: &str) -> Option<usize> {
+        self.users.get(name).copied()
+    }
+
+    /// The id of the article with the given name, if the article is in the database.
+    pub(crate) fn article_id(&self, name: &str) -> Option<usize> {
+        self.articles.get(name).copied()
+    }
+
+    /// The names of all users, indexed by user id.
+    pub(crate) fn user_names(&self) -> Vec<&str> {
+        let mut names = vec![""; self.users.len()];
+        for (name, &user_id) in &self.users {
+            names[user_id] = name;
+        }
+        names
+    }
+
+    /// The names of all articles, indexed by article id.
+    pub(crate) fn article_names(&self) -> Vec<&str> {
+        let mut names = vec![""; self.articles.len()];
+        for (name, &article_id) in &self.articles {
+            names[article_id] = name;
+        }
+        names
     }
 
     /// Returns the internal wikidot page id for a given article or none, if the article is not
@@ -219,94 +1500,287 @@ impl Database {
     }
 }
 
-/// A prediction model for the user votes. This is created from a database by training a linear
+/// A factor model of the user votes. This is created from a database by training a linear
 /// regression model to create the user_factors and article_factors matrices.
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub(crate) struct PredictionModel {
-    database: Database,
+pub(crate) struct FactorModel {
+    /// The database the model was trained on, shared with the other members of an ensemble.
+    pub(crate) database: Arc<Database>,
     user_factors: nalgebra::DMatrix<f64>,
     article_factors: nalgebra::DMatrix<f64>,
+
+    /// Global mean and user and article biases, if the model was trained with biases.
+    #[serde(default)]
+    biases: Option<Biases>,
+
+    /// Implicit factors, if the model was trained with SVD++.
+    #[serde(default)]
+    implicit_factors: Option<ImplicitFactors>,
+
+    /// The loss the model was trained with, which determines how scores are turned into votes.
+    #[serde(default)]
+    loss: Loss,
+
     user_votes: Vec<Vec<usize>>,
+
+    /// Votes that were held out from training for evaluation.
+    #[serde(default)]
+    held_out: Split,
+
+    /// How the number of factors was chosen, if it was chosen on the validation votes.
+    #[serde(default)]
+    rank_selection: Option<RankSelection>,
 }
 
-impl PredictionModel {
-    /// Loads the prediction model from file ``prediction_model.bin``.
-    pub fn load() -> Self {
-        let mut file =
-            File::open("prediction_model.bin").expect("Failed to open prediction model file.");
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer)
-            .expect("Failed to read prediction model from file.");
-        serde_cbor::from_slice(&buffer).unwrap()
-    }
+/// The number of latent factors of a model chosen on the validation votes, together with the
+/// validation errors it was chosen from.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct RankSelection {
+    /// The chosen number of factors, the one with the lowest validation error.
+    rank: usize,
 
-    /// Predicts the votes of a user for all articles and reports the `top` predictions to the console.
-    pub fn predict_for_user(&self, name: &str, top: usize) {
-        let user_id = if let Some(user_id) = self.database.users.get(name) {
-            user_id
-        } else {
-            println!("User {} not found.", name);
-            return;
-        };
+    /// The validation mean square error of the model with the first 1, 2, ... factors, up to the
+    /// last factor that was trained.
+    validation_errors: Vec<f64>,
+}
 
-        let user_factor = self.user_factors.row(*user_id);
+impl RankSelection {
+    /// Chooses the number of factors with the lowest of the `validation_errors`, preferring fewer
+    /// factors on ties.
+    fn new(validation_errors: Vec<f64>) -> Self {
+        let mut rank = 1;
+        for (index, &error) in validation_errors.iter().enumerate() {
+            if error < validation_errors[rank - 1] {
+                rank = index + 1;
+            }
+        }
+        Self {
+            rank,
+            validation_errors,
+        }
+    }
+}
 
-        let mut predictions = Vec::new();
-        for (article, article_id) in self.database.articles.iter() {
-            let article_factor = self.article_factors.row(*article_id);
-            let prediction = user_factor.dot(&article_factor);
-            predictions.push((article, prediction));
+impl std::fmt::Display for RankSelection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Selected {} of {} trained latent factors on the validation votes.",
+            self.rank,
+            self.validation_errors.len()
+        )?;
+        writeln!(f, "Validation mean square error by number of factors:")?;
+        for (index, error) in self.validation_errors.iter().enumerate() {
+            writeln!(f, "  {:>3}: {:.4}", index + 1, error)?;
         }
+        Ok(())
+    }
+}
 
-        let mut sorted_predictions = predictions
-            .iter()
-            .enumerate()
-            .filter(|(article_id, _)| !self.user_votes[*user_id].contains(article_id))
-            .map(|(_, (article, prediction))| (*article, *prediction))
-            .collect::<Vec<_>>();
+impl FactorModel {
+    /// Creates a factor model from trained factors. Rows of the factor matrices are indexed by
+    /// user and article id of `database`, the database the model was trained on.
+    pub(crate) fn new(
+        database: Database,
+        user_factors: nalgebra::DMatrix<f64>,
+        article_factors: nalgebra::DMatrix<f64>,
+        biases: Option<Biases>,
+        loss: Loss,
+        held_out: Split,
+    ) -> Self {
+        Self {
+            user_votes: database.user_votes(),
+            database: Arc::new(database),
+            user_factors,
+            article_factors,
+            biases,
+            implicit_factors: None,
+            loss,
+            held_out,
+            rank_selection: None,
+        }
+    }
 
-        sorted_predictions.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+    /// How the number of factors was chosen, if it was chosen on the validation votes.
+    pub(crate) fn rank_selection(&self) -> Option<&RankSelection> {
+        self.rank_selection.as_ref()
+    }
 
-        print!("User {} will most likely upvote those articles: ", name);
-        for (article, prediction) in sorted_predictions.iter().take(top) {
-            print!("{} (predicted vote: {:.2}), ", article, prediction);
+    /// Recomputes the values that are derived from the stored ones after loading the model.
+    pub(crate) fn aggregate(&mut self) {
+        if let Some(implicit) = &mut self.implicit_factors {
+            implicit.aggregate(&self.user_votes);
         }
-        println!();
     }
+}
 
-    /// Predicts the votes of all users for a given article and reports the `top` predictions to the console.
-    pub fn predict_for_article(&self, name: &str, top: usize) {
-        let article_id = if let Some(article_id) = self.database.articles.get(name) {
-            *article_id
-        } else {
-            println!("Article not found.");
-            return;
-        };
+impl Recommender for FactorModel {
+    fn database(&self) -> &Database {
+        &self.database
+    }
+
+    fn held_out(&self) -> &Split {
+        &self.held_out
+    }
 
+    fn voted_articles(&self, user_id: usize) -> &[usize] {
+        &self.user_votes[user_id]
+    }
+
+    fn predict(&self, user_id: usize, article_id: usize) -> f64 {
         let article_factor = self.article_factors.row(article_id);
-        let mut predictions = Vec::new();
-        for (user, user_id) in self.database.users.iter() {
-            let user_factor = self.user_factors.row(*user_id);
-            let prediction = user_factor.dot(&article_factor);
-            predictions.push((user, prediction, user_id));
+        let mut score = self.user_factors.row(user_id).dot(&article_factor);
+        if let Some(biases) = &self.biases {
+            score += biases.predict(user_id, article_id);
+        }
+        if let Some(implicit) = &self.implicit_factors {
+            score += implicit.user_sums.row(user_id).dot(&article_factor);
         }
+        self.loss.vote(score)
+    }
 
-        let mut sorted_predictions = predictions
-            .iter()
-            .filter(|(_, _, user_id)| {
-                !self.database.article_votes[article_id]
-                    .iter()
-                    .any(|(id, _)| id == *user_id)
-            })
-            .map(|(user, prediction, _)| (*user, *prediction))
-            .collect::<Vec<_>>();
-        //
-        sorted_predictions.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+    /// Models trained with the logistic loss predict calibrated probabilities, so they are
+    /// reported as such.
+    fn describe_prediction(&self, prediction: f64) -> String {
+        match self.loss {
+            Loss::Squared => format!("predicted vote: {:.2}", prediction),
+            Loss::Logistic => format!("upvote probability: {:.0}%", (prediction + 1.0) * 50.0),
+        }
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        serde_cbor::to_vec(&ModelRef::Factors(self)).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serializes the progress of a training and removes the given fields, as if the progress
+    /// had been saved by a version without them.
+    fn without_fields(progress: &Progress, fields: &[&str]) -> Vec<u8> {
+        let serde_cbor::Value::Map(mut map) = serde_cbor::value::to_value(progress).unwrap() else {
+            panic!("The progress is not serialized as a map.");
+        };
+        for field in fields {
+            assert!(map
+                .remove(&serde_cbor::Value::Text(field.to_string()))
+                .is_some());
+        }
+        serde_cbor::to_vec(&serde_cbor::Value::Map(map)).unwrap()
+    }
+
+    fn progress() -> Progress {
+        let state = FactorState {
+            user_factors: nalgebra::DMatrix::from_element(2, 3, 0.1),
+            article_factors: nalgebra::DMatrix::from_element(4, 3, 0.1),
+            implicit_factors: None,
+            biases: Biases::new(0.0, 2, 4),
+        };
+        Progress::new(state, Optimizer::Plain)
+    }
+
+    #[test]
+    fn checkpoints_without_moments_are_read() {
+        let buffer = without_fields(&progress(), &["moments"]);
+        let mut progress = serde_cbor::from_slice::<Progress>(&buffer).unwrap();
+        let mut gradient = nalgebra::DVector::from_element(2, 1.0);
+        progress
+            .moments
+            .user
+            .update(Optimizer::Plain, 0.5, &mut gradient);
+        assert_eq!(gradient, nalgebra::DVector::from_element(2, 0.5));
+    }
 
-        print!("{} will most likely be upvoted by: ", name);
-        for (user, prediction) in sorted_predictions.iter().take(top) {
-            println!("{} (predicted vote: {:.2}), ", user, prediction);
+    #[test]
+    fn adam_checkpoints_without_moments_start_the_optimizer_over() {
+        let mut database = Database::new();
+        database.add_user("a".to_string());
+        database.add_user("b".to_string());
+        for (article, votes) in [
+            vec![(0, true), (1, true)],
+            vec![(0, false)],
+            vec![(1, true)],
+            vec![(0, true), (1, false)],
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            database.add_article(format!("scp-{}", article), String::new(), votes);
         }
-        println!();
+        let parameters = TrainingParameters {
+            latent_factors: 3,
+            iterations: 5,
+            optimizer: Optimizer::Adam,
+            learning_rate: 0.01,
+            ..TrainingParameters::default()
+        };
+        let resume = |progress: Progress| {
+            database.clone().train_gradient_descent(
+                &parameters,
+                Split::default(),
+                &TrainingLog::default(),
+                Some(progress),
+                None,
+            )
+        };
+
+        let fresh = Progress::new(progress().state, Optimizer::Adam);
+        let buffer = without_fields(&fresh, &["moments"]);
+        let resumed = resume(serde_cbor::from_slice(&buffer).unwrap());
+        let expected = resume(fresh);
+        assert_eq!(resumed.user_factors, expected.user_factors);
+        assert_eq!(resumed.article_factors, expected.article_factors);
+        assert_ne!(
+            resumed.user_factors,
+            nalgebra::DMatrix::from_element(2, 3, 0.1)
+        );
+    }
+
+    #[test]
+    fn checkpoints_without_rank_errors_are_read() {
+        let buffer = without_fields(&progress(), &["rank_errors"]);
+        let progress = serde_cbor::from_slice::<Progress>(&buffer).unwrap();
+        assert!(progress.rank_errors.is_empty());
+    }
+
+    #[test]
+    fn errors_and_means_without_votes_are_undefined() {
+        let mut database = Database::new();
+        database.add_user("a".to_string());
+        database.add_article("scp-1".to_string(), String::new(), Vec::new());
+        assert_eq!(database.mean_vote(), None);
+        assert_eq!(
+            Database::mean_square_error(database.article_votes(), |_, _| 0.0),
+            None
+        );
+
+        database.add_article("scp-2".to_string(), String::new(), vec![(0, true)]);
+        assert_eq!(database.mean_vote(), Some(1.0));
+        assert_eq!(
+            Database::mean_square_error(database.article_votes(), |_, _| 0.5),
+            Some(0.25)
+        );
+    }
+
+    #[test]
+    fn rank_selection_prefers_fewer_factors() {
+        let selection = RankSelection::new(vec![0.9, 0.7, 0.8, 0.7, 0.75]);
+        assert_eq!(selection.rank, 2);
+        let selection = RankSelection::new(vec![0.9, 0.95]);
+        assert_eq!(selection.rank, 1);
+    }
+
+    #[test]
+    fn truncated_factors_predict_like_the_first_factors() {
+        let mut state = progress().state;
+        state.user_factors[(1, 2)] = 0.7;
+        state.article_factors[(3, 1)] = -0.4;
+        let expected = state.predict_rank(1, 3, 2);
+        state.truncate(2);
+        assert_eq!(state.user_factors.ncols(), 2);
+        assert!((state.predict(1, 3) - expected).abs() < 1e-12);
+        assert!((expected - (0.01 - 0.04)).abs() < 1e-12);
     }
 }
@@ -0,0 +1,346 @@
+use crate::database::{Database, TrainingParameters};
+use crate::evaluation::{Split, SplitStrategy};
+use crate::recommender::{Model, ModelRef, Recommender};
+use crate::report::TrainingLog;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize, Serializer};
+use std::sync::Arc;
+
+/// The ridge regularization of the blend weights per probe vote. It keeps the weights of members
+/// with nearly the same predictions from growing large with opposite signs, and keeps the fit
+/// solvable if members predict the same or constant votes.
+const BLEND_REGULARIZATION: f64 = 0.001;
+
+/// A linear blend of several prediction models, like the solutions that won the Netflix Prize.
+/// A vote is predicted as the intercept plus the weighted predictions of all members, so members
+/// that make different mistakes correct each other.
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct EnsembleModel {
+    /// The database all members were trained on, shared with them. The model file stores it only
+    /// here, the members are stored with empty databases.
+    pub(crate) database: Arc<Database>,
+
+    /// The blended models, all trained on the same votes.
+    members: Vec<Model>,
+
+    /// The name of each member, as given on the command line.
+    names: Vec<String>,
+
+    /// The weight of each member's predictions.
+    weights: Vec<f64>,
+
+    intercept: f64,
+
+    #[serde(default)]
+    held_out: Split,
+}
+
+impl EnsembleModel {
+    /// Shares the database with all members and recomputes the values that are derived from the
+    /// stored ones in them.
+    pub(crate) fn aggregate(&mut self) {
+        for member in &mut self.members {
+            member.set_database(self.database.clone());
+            member.aggregate();
+        }
+    }
+
+    /// Describes the weight of every member for the console.
+    fn describe_weights(&self) -> String {
+        let weights = self
+            .names
+            .iter()
+            .zip(&self.weights)
+            .map(|(name, weight)| format!("{} (weight {:.2})", name, weight))
+            .collect::<Vec<_>>();
+        format!("Blended from: {}", weights.join(", "))
+    }
+}
+
+/// Stores the database once, next to members without it. The members are copied one at a time to
+/// swap in an empty database, which does not copy the shared database.
+impl Serialize for EnsembleModel {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        /// The members of an ensemble, each stored with an empty database.
+        struct Members<'a>(&'a [Model]);
+
+        impl Serialize for Members<'_> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let empty = Arc::new(Database::new());
+                serializer.collect_seq(self.0.iter().map(|member| {
+                    let mut member = member.clone();
+                    member.set_database(empty.clone());
+                    member
+                }))
+            }
+        }
+
+        /// The fields of an ensemble, as they are read back into an [`EnsembleModel`].
+        #[derive(Serialize)]
+        struct Stored<'a> {
+            database: &'a Database,
+            members: Members<'a>,
+            names: &'a [String],
+            weights: &'a [f64],
+            intercept: f64,
+            held_out: &'a Split,
+        }
+
+        Stored {
+            database: &self.database,
+            members: Members(&self.members),
+            names: &self.names,
+            weights: &self.weights,
+            intercept: self.intercept,
+            held_out: &self.held_out,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl Recommender for EnsembleModel {
+    fn database(&self) -> &Database {
+        &self.database
+    }
+
+    fn held_out(&self) -> &Split {
+        &self.held_out
+    }
+
+    fn voted_articles(&self, user_id: usize) -> &[usize] {
+        self.members[0].voted_articles(user_id)
+    }
+
+    fn predict(&self, user_id: usize, article_id: usize) -> f64 {
+        self.members
+            .iter()
+            .zip(&self.weights)
+            .map(|(member, weight)| weight * member.predict(user_id, article_id))
+            .sum::<f64>()
+            + self.intercept
+    }
+
+    fn predict_articles(&self, user_id: usize, article_ids: &[usize]) -> Vec<f64> {
+        let mut predictions = vec![0.0; article_ids.len()];
+        for (member, weight) in self.members.iter().zip(&self.weights) {
+            let member_predictions = member.predict_articles(user_id, article_ids);
+            for (prediction, member_prediction) in predictions.iter_mut().zip(member_predictions) {
+                *prediction += weight * member_prediction;
+            }
+        }
+        predictions
+            .iter_mut()
+            .for_each(|prediction| *prediction += self.intercept);
+        predictions
+    }
+
+    fn predict_users(&self, article_id: usize, user_ids: &[usize]) -> Vec<f64> {
+        let mut predictions = vec![0.0; user_ids.len()];
+        for (member, weight) in self.members.iter().zip(&self.weights) {
+            let member_predictions = member.predict_users(article_id, user_ids);
+            for (prediction, member_prediction) in predictions.iter_mut().zip(member_predictions) {
+                *prediction += weight * member_prediction;
+            }
+        }
+        predictions
+            .iter_mut()
+            .for_each(|prediction| *prediction += self.intercept);
+        predictions
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        serde_cbor::to_vec(&ModelRef::Ensemble(self)).unwrap()
+    }
+
+    /// Lists the blend weights, followed by the explanations of the members that have one.
+    fn explain(&self, user_id: usize) -> Option<String> {
+        let mut explanation = self.describe_weights();
+        for member in &self.members {
+            if let Some(member_explanation) = member.explain(user_id) {
+                explanation.push('\n');
+                explanation.push_str(&member_explanation);
+            }
+        }
+        Some(explanation)
+    }
+}
+
+/// Trains a blend of the `members`, each given by its name and training parameters. A random
+/// `probe` fraction of the votes is held out, every member is trained on the remaining votes and
+/// the blend weights are fitted by ridge regression of the probe votes on the members' predictions.
+/// Since the members are better with all votes, they are then trained again on all votes, which
+/// is also what the Netflix Prize teams did with their probe set. Returns `None` if no votes are
+/// drawn for the probe set.
+pub(crate) fn train(
+    database: Database,
+    members: &[(String, TrainingParameters)],
+    probe: f64,
+    seed: u64,
+    held_out: Split,
+    log: &TrainingLog,
+) -> Option<EnsembleModel> {
+    let now = std::time::Instant::now();
+    let member_log = TrainingLog::new(log.verbose);
+
+    let (probe_database, probe_split) = Split::new(
+        &database,
+        SplitStrategy::Random {
+            validation: 0.0,
+            test: probe,
+        },
+        seed,
+    );
+    let votes = probe_split
+        .test
+        .iter()
+        .enumerate()
+        .flat_map(|(article_id, votes)| {
+            votes
+                .iter()
+                .map(move |&(user_id, vote)| (user_id, article_id, if vote { 1.0 } else { -1.0 }))
+        })
+        .collect::<Vec<_>>();
+    if votes.is_empty() {
+        return None;
+    }
+
+    // one column of predictions per member, followed by a column of ones for the intercept
+    let mut features = nalgebra::DMatrix::from_element(votes.len(), members.len() + 1, 1.0);
+    for (index, (name, parameters)) in members.iter().enumerate() {
+        if log.verbose {
+            println!(
+                "Training {} without the {} probe votes...",
+                name,
+                votes.len()
+            );
+        }
+        let model = probe_database.clone().train_prediction_model(
+            parameters,
+            Split::default(),
+            &member_log,
+        );
+        let predictions = votes
+            .par_iter()
+            .map(|&(user_id, article_id, _)| model.predict(user_id, article_id))
+            .collect::<Vec<_>>();
+        features.set_column(index, &nalgebra::DVector::from_vec(predictions));
+    }
+
+    let targets = nalgebra::DVector::from_iterator(votes.len(), votes.iter().map(|vote| vote.2));
+    let mut gram = features.transpose() * &features;
+    for index in 0..=members.len() {
+        gram[(index, index)] += BLEND_REGULARIZATION * votes.len() as f64;
+    }
+    let solution = gram
+        .cholesky()
+        .expect("Failed to fit the blend, the members predicted invalid votes.")
+        .solve(&(features.transpose() * targets));
+    let weights = solution.rows(0, members.len()).iter().copied().collect();
+    let intercept = solution[members.len()];
+
+    // every member is trained on its own copy of the votes, which is dropped in favor of the
+    // shared database once it is trained
+    let database = Arc::new(database);
+    let models = members
+        .iter()
+        .map(|(name, parameters)| {
+            if log.verbose {
+                println!("Training {} on all votes...", name);
+            }
+            let mut model = Database::clone(&database).train_prediction_model(
+                parameters,
+                Split::default(),
+                &member_log,
+            );
+            model.set_database(database.clone());
+            model
+        })
+        .collect();
+
+    let model = EnsembleModel {
+        database,
+        members: models,
+        names: members.iter().map(|(name, _)| name.clone()).collect(),
+        weights,
+        intercept,
+        held_out,
+    };
+    if log.verbose {
+        println!("{}", model.describe_weights());
+        println!("Training finished in {}ms.", now.elapsed().as_millis());
+    }
+    Some(model)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::ModelKind;
+
+    /// Twenty users voting on ten articles, upvoting unless the sum of their ids is divisible by
+    /// three.
+    fn database() -> Database {
+        let mut database = Database::new();
+        for user_id in 0..20 {
+            database.add_user(format!("user{}", user_id));
+        }
+        for article_id in 0..10 {
+            let votes = (0..20)
+                .map(|user_id| (user_id, (user_id + article_id) % 3 != 0))
+                .collect();
+            database.add_article(format!("scp-{}", article_id), String::new(), votes);
+        }
+        database
+    }
+
+    fn popularity() -> (String, TrainingParameters) {
+        let parameters = TrainingParameters {
+            model: ModelKind::Popularity,
+            ..TrainingParameters::default()
+        };
+        ("popularity".to_string(), parameters)
+    }
+
+    #[test]
+    fn identical_members_can_be_blended() {
+        let members = [popularity(), popularity()];
+        let log = TrainingLog::default();
+        let model = train(database(), &members, 0.3, 0, Split::default(), &log).unwrap();
+        assert!(model.weights.iter().all(|weight| weight.is_finite()));
+        assert!((model.weights[0] - model.weights[1]).abs() < 1e-9);
+    }
+
+    #[test]
+    fn empty_probe_set_is_rejected() {
+        let members = [popularity()];
+        let log = TrainingLog::default();
+        assert!(train(database(), &members, 1e-9, 0, Split::default(), &log).is_none());
+    }
+
+    #[test]
+    fn model_file_stores_the_database_once() {
+        let members = [popularity(), popularity()];
+        let log = TrainingLog::default();
+        let model = train(database(), &members, 0.3, 0, Split::default(), &log).unwrap();
+        let article_ids = (0..10).collect::<Vec<_>>();
+        let predictions = model.predict_articles(3, &article_ids);
+        for (&article_id, &prediction) in article_ids.iter().zip(&predictions) {
+            assert!((model.predict(3, article_id) - prediction).abs() < 1e-12);
+        }
+
+        let Model::Ensemble(mut loaded) =
+            serde_cbor::from_slice(&Recommender::serialize(&model)).unwrap()
+        else {
+            panic!("The model file does not hold an ensemble.");
+        };
+        for member in &loaded.members {
+            assert_eq!(member.database().article_count(), 0);
+        }
+        loaded.aggregate();
+        assert_eq!(loaded.members[1].database().article_count(), 10);
+        for member in &loaded.members {
+            assert!(std::ptr::eq(member.database(), loaded.database()));
+        }
+        assert_eq!(loaded.predict_articles(3, &article_ids), predictions);
+    }
+}
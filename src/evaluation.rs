@@ -0,0 +1,632 @@
+use crate::database::{Database, Propensity};
+use crate::recommender::Recommender;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rayon::iter::{
+    IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelIterator,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A list of votes indexed by article id, like the votes stored in the [`Database`]. Each entry is
+/// a tuple of the user id and the vote.
+pub(crate) type Votes = Vec<Vec<(usize, bool)>>;
+
+/// Strategy used to hold out votes from training, so a model can be evaluated on votes it has not
+/// seen.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub(crate) enum SplitStrategy {
+    /// Every vote is independently held out for validation or testing with the given
+    /// probabilities.
+    Random { validation: f64, test: f64 },
+
+    /// For every user, the given number of randomly chosen votes is held out for validation and
+    /// testing. Users with too few votes to keep at least one training vote are not held out.
+    LeaveKOut { validation: usize, test: usize },
+
+    /// All votes of the most recently added articles are held out. The given fractions of
+    /// articles are held out for validation and testing, the newest ones being used for testing.
+    ArticleOrder { validation: f64, test: f64 },
+}
+
+/// Which part of a split a vote belongs to.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Part {
+    Train,
+    Validation,
+    Test,
+}
+
+/// Votes held out from a database for validation and testing.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub(crate) struct Split {
+    /// Held out votes used to tune training, for example to decide when to stop.
+    pub(crate) validation: Votes,
+
+    /// Held out votes used to evaluate the final model.
+    pub(crate) test: Votes,
+}
+
+impl Split {
+    /// Splits the votes of `database` according to `strategy`. Returns a database containing only
+    /// the training votes, and the held out votes. The split is deterministic for a given `seed`.
+    pub(crate) fn new(database: &Database, strategy: SplitStrategy, seed: u64) -> (Database, Self) {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let article_votes = database.article_votes();
+        let mut parts: Vec<Vec<Part>> = article_votes
+            .iter()
+            .map(|votes| vec![Part::Train; votes.len()])
+            .collect();
+
+        match strategy {
+            SplitStrategy::Random { validation, test } => {
+                for part in parts.iter_mut().flatten() {
+                    let sample = rng.gen::<f64>();
+                    if sample < test {
+                        *part = Part::Test;
+                    } else if sample < test + validation {
+                        *part = Part::Validation;
+                    }
+                }
+            }
+            SplitStrategy::LeaveKOut { validation, test } => {
+                // positions of each user's votes as (article id, index in the article's votes)
+                let mut user_votes = vec![Vec::new(); database.user_count()];
+                for (article_id, votes) in article_votes.iter().enumerate() {
+                    for (index, &(user_id, _)) in votes.iter().enumerate() {
+                        user_votes[user_id].push((article_id, index));
+                    }
+                }
+
+                for votes in user_votes.iter_mut() {
+                    if votes.len() <= validation + test {
+                        continue;
+                    }
+
+                    votes.shuffle(&mut rng);
+                    for (position, &(article_id, index)) in
+                        votes.iter().take(validation + test).enumerate()
+                    {
+                        parts[article_id][index] = if position < test {
+                            Part::Test
+                        } else {
+                            Part::Validation
+                        };
+                    }
+                }
+            }
+            SplitStrategy::ArticleOrder { validation, test } => {
+                let articles = article_votes.len();
+                let test_start = articles.saturating_sub((articles as f64 * test).round() as usize);
+                let validation_start =
+                    test_start.saturating_sub((articles as f64 * validation).round() as usize);
+
+                for (article_id, article_parts) in parts.iter_mut().enumerate() {
+                    let part = if article_id >= test_start {
+                        Part::Test
+                    } else if article_id >= validation_start {
+                        Part::Validation
+                    } else {
+                        Part::Train
+                    };
+                    article_parts.fill(part);
+                }
+            }
+        }
+
+        let select = |selected: Part| -> Votes {
+            article_votes
+                .iter()
+                .zip(parts.iter())
+                .map(|(votes, parts)| {
+                    votes
+                        .iter()
+                        .zip(parts.iter())
+                        .filter(|(_, &part)| part == selected)
+                        .map(|(&vote, _)| vote)
+                        .collect()
+                })
+                .collect()
+        };
+
+        let split = Self {
+            validation: select(Part::Validation),
+            test: select(Part::Test),
+        };
+        (database.with_votes(select(Part::Train)), split)
+    }
+
+    /// Divides the votes of `database` randomly into `folds` equally sized folds for
+    /// cross-validation. For every fold, returns a database containing the votes of all other
+    /// folds and a split holding out the fold's votes for validation.
+    pub(crate) fn folds(database: &Database, folds: usize, seed: u64) -> Vec<(Database, Self)> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let article_votes = database.article_votes();
+
+        // assign each vote to a fold, balancing the fold sizes by shuffling a repeating sequence
+        let mut assignment = (0..database.total_votes())
+            .map(|index| index % folds)
+            .collect::<Vec<_>>();
+        assignment.shuffle(&mut rng);
+
+        let mut assignment = assignment.into_iter();
+        let vote_folds: Vec<Vec<usize>> = article_votes
+            .iter()
+            .map(|votes| assignment.by_ref().take(votes.len()).collect())
+            .collect();
+
+        (0..folds)
+            .map(|fold| {
+                let (train, validation): (Votes, Votes) = article_votes
+                    .iter()
+                    .zip(vote_folds.iter())
+                    .map(|(votes, vote_folds)| {
+                        let (validation, train): (Vec<_>, Vec<_>) = votes
+                            .iter()
+                            .zip(vote_folds.iter())
+                            .partition(|(_, &vote_fold)| vote_fold == fold);
+                        (
+                            train.into_iter().map(|(&vote, _)| vote).collect::<Vec<_>>(),
+                            validation
+                                .into_iter()
+                                .map(|(&vote, _)| vote)
+                                .collect::<Vec<_>>(),
+                        )
+                    })
+                    .unzip();
+
+                let split = Self {
+                    validation,
+                    test: Votes::new(),
+                };
+                (database.with_votes(train), split)
+            })
+            .collect()
+    }
+
+    /// Whether any votes have been held out for validation.
+    pub(crate) fn has_validation(&self) -> bool {
+        self.validation.iter().any(|votes| !votes.is_empty())
+    }
+
+    /// Whether any votes have been held out for testing.
+    pub(crate) fn has_test(&self) -> bool {
+        self.test.iter().any(|votes| !votes.is_empty())
+    }
+}
+
+/// Quality metrics of a prediction model on a set of held out votes.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub(crate) struct Metrics {
+    /// Root mean square error of the predicted scores against the votes (+1 and -1).
+    pub(crate) rmse: f64,
+
+    /// Mean absolute error of the predicted scores against the votes.
+    pub(crate) mae: f64,
+
+    /// Area under the ROC curve, i.e. the probability that a random held out upvote is scored
+    /// higher than a random held out downvote. Undefined (NaN) without held out upvotes or
+    /// downvotes.
+    pub(crate) auc: f64,
+
+    /// Mean fraction of the top `k` recommendations per user that are held out upvotes.
+    pub(crate) precision: f64,
+
+    /// Mean fraction of held out upvotes per user that appear in the top `k` recommendations.
+    pub(crate) recall: f64,
+
+    /// Mean average precision of the top `k` recommendations.
+    pub(crate) map: f64,
+
+    /// Mean normalized discounted cumulative gain of the top `k` recommendations.
+    pub(crate) ndcg: f64,
+
+    /// Root mean square error with every held out vote weighted by the inverse propensity of its
+    /// article, normalized by the sum of the weights (self-normalized inverse propensity scoring).
+    /// Estimates the error as if all articles were seen equally often.
+    pub(crate) debiased_rmse: f64,
+
+    /// Mean recall of the top `k` recommendations with every held out upvote weighted by the
+    /// inverse propensity of its article, so finding rarely seen articles counts more.
+    pub(crate) debiased_recall: f64,
+
+    /// The propensity estimate the debiased metrics weight the held out votes with.
+    pub(crate) propensity: Propensity,
+
+    /// The cut-off rank of the ranking metrics.
+    pub(crate) k: usize,
+
+    /// The number of held out votes the rating metrics were computed on.
+    pub(crate) votes: usize,
+
+    /// The number of users with held out upvotes the ranking metrics were averaged over.
+    pub(crate) users: usize,
+}
+
+impl Metrics {
+    /// Evaluates `model` on the held out `votes`. Rating metrics compare the predicted score of
+    /// every held out vote with the actual vote. Ranking metrics rank all articles each user has
+    /// not voted on during training and check where the user's held out upvotes end up. The
+    /// debiased metrics estimate the `propensity` of every article from the training votes of the
+    /// model.
+    pub(crate) fn evaluate(
+        model: &impl Recommender,
+        votes: &Votes,
+        k: usize,
+        propensity: Propensity,
+    ) -> Self {
+        let propensities = model.database().propensities(propensity);
+        let mut user_votes = vec![Vec::new(); model.database().user_count()];
+        for (article_id, article_votes) in votes.iter().enumerate() {
+            for &(user_id, vote) in article_votes {
+                user_votes[user_id].push((article_id, vote));
+            }
+        }
+
+        // the held out votes of each user are predicted together
+        let predictions = user_votes
+            .par_iter()
+            .enumerate()
+            .map(|(user_id, votes)| {
+                let article_ids = votes
+                    .iter()
+                    .map(|&(article_id, _)| article_id)
+                    .collect::<Vec<_>>();
+                model.predict_articles(user_id, &article_ids)
+            })
+            .collect::<Vec<_>>();
+        let mut scored = Vec::new();
+        let mut weights = Vec::new();
+        let mut relevant = vec![Vec::new(); user_votes.len()];
+        for (user_id, (votes, predictions)) in user_votes.iter().zip(predictions).enumerate() {
+            for (&(article_id, vote), prediction) in votes.iter().zip(predictions) {
+                scored.push((prediction, vote));
+                weights.push(1.0 / propensities[article_id]);
+                if vote {
+                    relevant[user_id].push(article_id);
+                }
+            }
+        }
+
+        let (mut square_error, mut absolute_error) = (0.0, 0.0);
+        let (mut weighted_square_error, mut total_weight) = (0.0, 0.0);
+        for (&(score, vote), &weight) in scored.iter().zip(&weights) {
+            let error = if vote { 1.0 } else { -1.0 } - score;
+            square_error += error * error;
+            absolute_error += error.abs();
+            weighted_square_error += weight * error * error;
+            total_weight += weight;
+        }
+
+        let (precision, recall, map, ndcg, debiased_recall, users) = relevant
+            .into_par_iter()
+            .enumerate()
+            .filter(|(_, relevant)| !relevant.is_empty())
+            .map(|(user_id, relevant)| {
+                let ranking = Self::rank(model, user_id, k);
+                let mut hits = 0;
+                let mut weighted_hits = 0.0;
+                let mut average_precision = 0.0;
+                let mut dcg = 0.0;
+                for (rank, article_id) in ranking.iter().enumerate() {
+                    if relevant.contains(article_id) {
+                        hits += 1;
+                        weighted_hits += 1.0 / propensities[*article_id];
+                        average_precision += hits as f64 / (rank + 1) as f64;
+                        dcg += 1.0 / (rank as f64 + 2.0).log2();
+                    }
+                }
+                let ideal_dcg: f64 = (0..relevant.len().min(k))
+                    .map(|rank| 1.0 / (rank as f64 + 2.0).log2())
+                    .sum();
+                let relevant_weight: f64 = relevant
+                    .iter()
+                    .map(|&article_id| 1.0 / propensities[article_id])
+                    .sum();
+
+                (
+                    hits as f64 / k as f64,
+                    hits as f64 / relevant.len() as f64,
+                    average_precision / relevant.len().min(k) as f64,
+                    dcg / ideal_dcg,
+                    weighted_hits / relevant_weight,
+                    1,
+                )
+            })
+            .reduce(
+                || (0.0, 0.0, 0.0, 0.0, 0.0, 0),
+                |a, b| {
+                    (
+                        a.0 + b.0,
+                        a.1 + b.1,
+                        a.2 + b.2,
+                        a.3 + b.3,
+                        a.4 + b.4,
+                        a.5 + b.5,
+                    )
+                },
+            );
+
+        Self {
+            rmse: (square_error / scored.len() as f64).sqrt(),
+            mae: absolute_error / scored.len() as f64,
+            auc: Self::auc(&mut scored),
+            precision: precision / users as f64,
+            recall: recall / users as f64,
+            map: map / users as f64,
+            ndcg: ndcg / users as f64,
+            debiased_rmse: (weighted_square_error / total_weight).sqrt(),
+            debiased_recall: debiased_recall / users as f64,
+            propensity,
+            k,
+            votes: scored.len(),
+            users,
+        }
+    }
+
+    /// Averages the metrics of several evaluations, for example of the folds of a
+    /// cross-validation. Metrics that are undefined in some evaluations are averaged over the
+    /// others. Counts are summed instead.
+    pub(crate) fn mean(metrics: &[Metrics]) -> Self {
+        let mean = |value: fn(&Metrics) -> f64| {
+            let defined = metrics
+                .iter()
+                .map(value)
+                .filter(|value| !value.is_nan())
+                .collect::<Vec<_>>();
+            defined.iter().sum::<f64>() / defined.len() as f64
+        };
+        Self {
+            rmse: mean(|m| m.rmse),
+            mae: mean(|m| m.mae),
+            auc: mean(|m| m.auc),
+            precision: mean(|m| m.precision),
+            recall: mean(|m| m.recall),
+            map: mean(|m| m.map),
+            ndcg: mean(|m| m.ndcg),
+            debiased_rmse: mean(|m| m.debiased_rmse),
+            debiased_recall: mean(|m| m.debiased_recall),
+            propensity: metrics
+                .first()
+                .map_or_else(Propensity::default, |m| m.propensity),
+            k: metrics.first().map_or(0, |m| m.k),
+            votes: metrics.iter().map(|m| m.votes).sum(),
+            users: metrics.iter().map(|m| m.users).sum(),
+        }
+    }
+
+    /// Returns the ids of the `k` highest scored articles the user has not voted on in the
+    /// training data, best first.
+    fn rank(model: &impl Recommender, user_id: usize, k: usize) -> Vec<usize> {
+        model
+            .top_articles(user_id, k)
+            .into_iter()
+            .map(|(article_id, _)| article_id)
+            .collect()
+    }
+
+    /// Computes the area under the ROC curve of the scored votes with the rank-sum formula. Tied
+    /// scores are assigned their average rank. Returns NaN if the votes are all upvotes or all
+    /// downvotes, since the area is undefined then.
+    fn auc(scored: &mut [(f64, bool)]) -> f64 {
+        scored.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let positives = scored.iter().filter(|(_, vote)| *vote).count() as f64;
+        let negatives = scored.len() as f64 - positives;
+        if positives == 0.0 || negatives == 0.0 {
+            return f64::NAN;
+        }
+
+        let mut positive_rank_sum = 0.0;
+        let mut start = 0;
+        while start < scored.len() {
+            let mut end = start + 1;
+            while end < scored.len() && scored[end].0 == scored[start].0 {
+                end += 1;
+            }
+
+            // ranks are 1-based, so the tied scores in start..end share rank (start + end + 1) / 2
+            let average_rank = (start + end + 1) as f64 / 2.0;
+            let tied_positives = scored[start..end].iter().filter(|(_, vote)| *vote).count();
+            positive_rank_sum += average_rank * tied_positives as f64;
+            start = end;
+        }
+
+        (positive_rank_sum - positives * (positives + 1.0) / 2.0) / (positives * negatives)
+    }
+}
+
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Rating metrics on {} held out votes:", self.votes)?;
+        writeln!(f, "  RMSE: {}", format_metric(self.rmse))?;
+        writeln!(f, "  MAE:  {}", format_metric(self.mae))?;
+        writeln!(f, "  AUC:  {}", format_metric(self.auc))?;
+        writeln!(
+            f,
+            "Ranking metrics on {} users with held out upvotes:",
+            self.users
+        )?;
+        writeln!(
+            f,
+            "  Precision@{}: {}",
+            self.k,
+            format_metric(self.precision)
+        )?;
+        writeln!(f, "  Recall@{}:    {}", self.k, format_metric(self.recall))?;
+        writeln!(f, "  MAP@{}:       {}", self.k, format_metric(self.map))?;
+        writeln!(f, "  NDCG@{}:      {}", self.k, format_metric(self.ndcg))?;
+        let propensity = match self.propensity {
+            Propensity::Uniform => "uniform",
+            Propensity::Popularity => "popularity",
+            Propensity::Age => "age",
+            Propensity::Series => "series",
+        };
+        writeln!(
+            f,
+            "Debiased metrics, weighted by the inverse {} propensity of the articles:",
+            propensity
+        )?;
+        writeln!(f, "  RMSE:        {}", format_metric(self.debiased_rmse))?;
+        write!(
+            f,
+            "  Recall@{}:   {}",
+            self.k,
+            format_metric(self.debiased_recall)
+        )
+    }
+}
+
+/// Formats a metric for the console. Metrics that are undefined for the held out votes, like the
+/// AUC without held out downvotes or the ranking metrics without held out upvotes, are NaN and
+/// shown as "n/a".
+pub(crate) fn format_metric(value: f64) -> String {
+    if value.is_nan() {
+        "n/a".to_string()
+    } else {
+        format!("{:.4}", value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A model with fixed predictions, indexed by user id and article id.
+    struct FixedModel {
+        database: Database,
+        held_out: Split,
+        user_votes: Vec<Vec<usize>>,
+        predictions: Vec<Vec<f64>>,
+    }
+
+    impl Recommender for FixedModel {
+        fn database(&self) -> &Database {
+            &self.database
+        }
+
+        fn held_out(&self) -> &Split {
+            &self.held_out
+        }
+
+        fn voted_articles(&self, user_id: usize) -> &[usize] {
+            &self.user_votes[user_id]
+        }
+
+        fn predict(&self, user_id: usize, article_id: usize) -> f64 {
+            self.predictions[user_id][article_id]
+        }
+
+        fn serialize(&self) -> Vec<u8> {
+            Vec::new()
+        }
+    }
+
+    /// Three users and four articles with one training upvote each, so the popularity
+    /// propensities of all articles are equal.
+    fn model() -> FixedModel {
+        let mut database = Database::new();
+        for user in ["a", "b", "c"] {
+            database.add_user(user.to_string());
+        }
+        for (article, user_id) in [("scp-1", 0), ("scp-2", 1), ("scp-3", 2), ("scp-4", 2)] {
+            database.add_article(article.to_string(), String::new(), vec![(user_id, true)]);
+        }
+        let user_votes = database.user_votes();
+        FixedModel {
+            database,
+            held_out: Split::default(),
+            user_votes,
+            predictions: vec![
+                vec![0.9, 0.5, 0.8, -0.2],
+                vec![0.1, 0.7, 0.4, 0.6],
+                vec![0.0; 4],
+            ],
+        }
+    }
+
+    #[test]
+    fn evaluate_computes_hand_computed_metrics() {
+        let votes = vec![
+            vec![(1, false)],
+            vec![(0, true)],
+            vec![(0, false)],
+            vec![(0, true), (1, true)],
+        ];
+        let metrics = Metrics::evaluate(&model(), &votes, 2, Propensity::Popularity);
+
+        // errors 0.5, -1.8, 1.2, -1.1 and 0.4
+        assert!((metrics.rmse - 1.26f64.sqrt()).abs() < 1e-12);
+        assert!((metrics.mae - 1.0).abs() < 1e-12);
+        // the upvotes scored 0.5, -0.2 and 0.6 beat the downvotes scored 0.8 and 0.1 twice
+        assert!((metrics.auc - 2.0 / 6.0).abs() < 1e-12);
+
+        // user a gets articles 3 and 2 with the upvote on 2 at rank 2 and misses the one on 4,
+        // user b gets articles 4 and 3 with the upvote on 4 at rank 1
+        assert_eq!(metrics.users, 2);
+        assert!((metrics.precision - 0.5).abs() < 1e-12);
+        assert!((metrics.recall - 0.75).abs() < 1e-12);
+        assert!((metrics.map - (0.25 + 1.0) / 2.0).abs() < 1e-12);
+        let ndcg_a = (1.0 / 3f64.log2()) / (1.0 + 1.0 / 3f64.log2());
+        assert!((metrics.ndcg - (ndcg_a + 1.0) / 2.0).abs() < 1e-12);
+
+        // all propensities are equal, so the debiased metrics equal the plain ones
+        assert!((metrics.debiased_rmse - metrics.rmse).abs() < 1e-12);
+        assert!((metrics.debiased_recall - metrics.recall).abs() < 1e-12);
+    }
+
+    #[test]
+    fn evaluate_without_upvotes_leaves_ranking_metrics_undefined() {
+        let votes = vec![vec![(1, false)], vec![(0, false)], Vec::new(), Vec::new()];
+        let metrics = Metrics::evaluate(&model(), &votes, 2, Propensity::Popularity);
+
+        assert_eq!(metrics.users, 0);
+        assert!(metrics.auc.is_nan());
+        assert!(metrics.precision.is_nan());
+        assert!(metrics.ndcg.is_nan());
+        assert_eq!(format_metric(metrics.auc), "n/a");
+        assert!(!metrics.to_string().contains("NaN"));
+    }
+
+    #[test]
+    fn auc_assigns_tied_scores_their_average_rank() {
+        // one upvote ties with one of the two downvotes, the other upvote beats both
+        let mut scored = [(0.5, true), (0.5, false), (0.2, false), (0.9, true)];
+        assert!((Metrics::auc(&mut scored) - 3.5 / 4.0).abs() < 1e-12);
+
+        let mut scored = [(1.0, true), (1.0, false), (1.0, true), (1.0, false)];
+        assert!((Metrics::auc(&mut scored) - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn auc_terminates_on_nan_scores() {
+        let mut scored = [
+            (f64::NAN, true),
+            (f64::NAN, false),
+            (0.3, true),
+            (0.1, false),
+        ];
+        let auc = Metrics::auc(&mut scored);
+        assert!((0.0..=1.0).contains(&auc));
+    }
+
+    #[test]
+    fn auc_is_undefined_for_a_single_class() {
+        assert!(Metrics::auc(&mut [(0.4, true), (0.8, true)]).is_nan());
+        assert!(Metrics::auc(&mut [(0.4, false)]).is_nan());
+    }
+
+    #[test]
+    fn mean_skips_undefined_metrics() {
+        let defined = Metrics {
+            auc: 0.8,
+            ..Metrics::default()
+        };
+        let undefined = Metrics {
+            auc: f64::NAN,
+            ..Metrics::default()
+        };
+        assert!((Metrics::mean(&[defined, undefined]).auc - 0.8).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,57 @@
+use nalgebra::DMatrix;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A matrix that can be updated from multiple threads without locking. The values are stored as
+/// the bits of `f64`s in atomics, so concurrent reads and writes are well-defined, but an update
+/// is a separate load and store and may be lost when another thread updates the same value in
+/// between.
+pub(crate) struct SharedMatrix {
+    rows: usize,
+    columns: usize,
+    values: Vec<AtomicU64>,
+}
+
+impl SharedMatrix {
+    /// Creates a shared matrix with the values of `matrix`.
+    pub(crate) fn new(matrix: &DMatrix<f64>) -> Self {
+        // the values are stored row by row, which is the column order of the transpose
+        Self {
+            rows: matrix.nrows(),
+            columns: matrix.ncols(),
+            values: matrix
+                .transpose()
+                .iter()
+                .map(|value| AtomicU64::new(value.to_bits()))
+                .collect(),
+        }
+    }
+
+    /// Reads the value at the given position.
+    pub(crate) fn get(&self, row: usize, column: usize) -> f64 {
+        f64::from_bits(self.values[row * self.columns + column].load(Ordering::Relaxed))
+    }
+
+    /// Overwrites the value at the given position.
+    pub(crate) fn set(&self, row: usize, column: usize, value: f64) {
+        self.values[row * self.columns + column].store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Adds `delta` to the value at the given position.
+    pub(crate) fn add(&self, row: usize, column: usize, delta: f64) {
+        let value = &self.values[row * self.columns + column];
+        let sum = f64::from_bits(value.load(Ordering::Relaxed)) + delta;
+        value.store(sum.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Copies a row into `buffer`.
+    pub(crate) fn read_row(&self, row: usize, buffer: &mut [f64]) {
+        for (column, value) in buffer.iter_mut().enumerate() {
+            *value = self.get(row, column);
+        }
+    }
+
+    /// Copies the current values into a matrix.
+    pub(crate) fn to_matrix(&self) -> DMatrix<f64> {
+        DMatrix::from_fn(self.rows, self.columns, |row, column| self.get(row, column))
+    }
+}
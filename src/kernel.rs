@@ -0,0 +1,524 @@
+use crate::database::{Biases, Loss};
+use nalgebra::{DMatrix, DVector};
+use rayon::iter::{
+    IndexedParallelIterator, IntoParallelRefIterator, IntoParallelRefMutIterator, ParallelIterator,
+};
+use rayon::slice::{ParallelSlice, ParallelSliceMut};
+use serde::{Deserialize, Serialize};
+use std::ops::{Add, AddAssign, Mul};
+
+/// The number of partial sums of a dot product that are computed independently. They do not
+/// depend on each other, so the compiler is free to vectorize the loop and compute several
+/// products at once.
+const LANES: usize = 8;
+
+/// The number of chunks of articles whose votes are walked in parallel. Each chunk sums up the
+/// gradients of the users in its own buffer, so the buffers take `CHUNKS` times the memory of the
+/// gradients of all users. The number is fixed, so the gradients are summed up in the same order
+/// whatever the number of threads.
+const CHUNKS: usize = 64;
+
+/// The floating point precision full-batch gradient descent computes the gradients in. The model
+/// is always stored with double precision, only the copies the gradients are computed from are
+/// rounded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum Precision {
+    /// 32-bit floats, which fit twice as many values into every cache line and vector register.
+    /// They round the gradients, but far less than the noise of the votes.
+    #[default]
+    Single,
+
+    /// 64-bit floats, as precise as the model.
+    Double,
+}
+
+impl Precision {
+    /// The precision of checkpoints written before it could be chosen, which always computed the
+    /// gradients in double precision.
+    pub(crate) fn legacy() -> Self {
+        Precision::Double
+    }
+}
+
+/// A floating point type the kernel computes in.
+pub(crate) trait Scalar:
+    Copy + Default + Send + Sync + Add<Output = Self> + Mul<Output = Self> + AddAssign
+{
+    fn from_f64(value: f64) -> Self;
+    fn to_f64(self) -> f64;
+}
+
+impl Scalar for f32 {
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl Scalar for f64 {
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+}
+
+/// Runs `f` inside a function compiled with AVX2 enabled if the CPU supports it, and as compiled
+/// for the baseline of the target otherwise. This is no explicit SIMD: it only allows the
+/// compiler to use the wider AVX registers if it inlines `f` and auto-vectorizes its loops, which
+/// is up to the optimizer. The call is `unsafe` because executing AVX2 instructions on a CPU
+/// without them is undefined behavior, which the runtime check rules out.
+#[inline(always)]
+fn with_avx2<R>(f: impl FnOnce() -> R) -> R {
+    #[cfg(target_arch = "x86_64")]
+    if std::is_x86_feature_detected!("avx2") {
+        #[target_feature(enable = "avx2")]
+        fn avx2<R>(f: impl FnOnce() -> R) -> R {
+            f()
+        }
+        // SAFETY: the CPU supports AVX2, as checked above
+        return unsafe { avx2(f) };
+    }
+    f()
+}
+
+/// Computes the dot product of two slices of the same length in [`LANES`] partial sums, which
+/// are only added up at the end.
+#[inline(always)]
+fn dot<T: Scalar>(a: &[T], b: &[T]) -> T {
+    let (a_chunks, b_chunks) = (a.chunks_exact(LANES), b.chunks_exact(LANES));
+    let mut sum = a_chunks
+        .remainder()
+        .iter()
+        .zip(b_chunks.remainder())
+        .fold(T::default(), |sum, (&a, &b)| sum + a * b);
+
+    let mut sums = [T::default(); LANES];
+    for (a, b) in a_chunks.zip(b_chunks) {
+        for lane in 0..LANES {
+            sums[lane] += a[lane] * b[lane];
+        }
+    }
+    for partial in sums {
+        sum += partial;
+    }
+    sum
+}
+
+/// A matrix stored row by row, so the factors of a user or an article are contiguous in memory,
+/// unlike in the column-major matrices of nalgebra.
+struct RowMatrix<T> {
+    columns: usize,
+    values: Vec<T>,
+}
+
+impl<T: Scalar> RowMatrix<T> {
+    /// Creates an empty matrix.
+    fn new() -> Self {
+        Self {
+            columns: 0,
+            values: Vec::new(),
+        }
+    }
+
+    /// Overwrites the matrix with a copy of `matrix`, rounding its values to `T`. The memory of
+    /// the matrix is reused.
+    fn copy_from(&mut self, matrix: &DMatrix<f64>) {
+        self.columns = matrix.ncols();
+        self.values.clear();
+        for row in 0..matrix.nrows() {
+            for column in 0..matrix.ncols() {
+                self.values.push(T::from_f64(matrix[(row, column)]));
+            }
+        }
+    }
+
+    /// Sets all values of a column to zero.
+    fn clear_column(&mut self, column: usize) {
+        for row in self.values.chunks_exact_mut(self.columns) {
+            row[column] = T::default();
+        }
+    }
+
+    /// The values of a row.
+    fn row(&self, row: usize) -> &[T] {
+        &self.values[row * self.columns..(row + 1) * self.columns]
+    }
+}
+
+/// The gradients of the factor and the bias of a user or an article.
+#[derive(Clone, Copy, Debug, Default)]
+struct Sums<T> {
+    factor: T,
+    bias: T,
+}
+
+/// The factor being trained and the bias of every user or article, next to each other so both
+/// are read from the same cache line.
+type Values<T> = Vec<(T, T)>;
+
+/// Computes the gradients of full-batch gradient descent in the precision `T`.
+///
+/// Only one factor is trained at a time, so at the start of every factor the kernel computes the
+/// score of all other factors for every vote, with dot products of row-major copies of the
+/// factors. An iteration then only adds the product of the trained factor and the biases to the
+/// score of every vote. The votes are walked in [`CHUNKS`] chunks of articles, each of which sums
+/// up the gradients of the users in its own buffer. The buffers are allocated once for the whole
+/// training and added up in order, so the gradients do not depend on the number of threads.
+pub(crate) struct Kernel<T> {
+    users: RowMatrix<T>,
+    articles: RowMatrix<T>,
+
+    global_mean: T,
+    user_values: Values<T>,
+    article_values: Values<T>,
+
+    /// The votes of each article, as the user id and the vote of 1 or -1.
+    votes: Vec<Vec<(u32, T)>>,
+
+    /// The dot product of the factors of every vote except the one being trained, indexed like
+    /// `votes`. It does not change while a factor is trained, so a vote only costs a single
+    /// product per iteration instead of a whole dot product.
+    scores: Vec<Vec<T>>,
+
+    /// The number of articles per chunk.
+    chunk_size: usize,
+
+    /// The gradients of every user in the votes of each chunk.
+    chunk_sums: Vec<Vec<Sums<T>>>,
+
+    user_sums: Vec<Sums<T>>,
+    article_sums: Vec<Sums<T>>,
+
+    /// The weighted square error of the votes of each article in the last iteration.
+    square_errors: Vec<f64>,
+}
+
+impl<T: Scalar> Kernel<T> {
+    /// Creates a kernel for the `article_votes` of a database with the given number of users. The
+    /// user ids of the votes are stored as 32-bit integers to save memory bandwidth.
+    pub(crate) fn new(article_votes: &[Vec<(usize, bool)>], users: usize) -> Self {
+        let articles = article_votes.len();
+        let chunk_size = articles.div_ceil(CHUNKS).max(1);
+        Self {
+            users: RowMatrix::new(),
+            articles: RowMatrix::new(),
+            global_mean: T::default(),
+            user_values: vec![Default::default(); users],
+            article_values: vec![Default::default(); articles],
+            votes: article_votes
+                .iter()
+                .map(|votes| {
+                    votes
+                        .iter()
+                        .map(|&(user_id, vote)| {
+                            let user_id = u32::try_from(user_id).expect(
+                                "Failed to index the votes, there are more than u32::MAX users.",
+                            );
+                            (user_id, T::from_f64(if vote { 1.0 } else { -1.0 }))
+                        })
+                        .collect()
+                })
+                .collect(),
+            scores: article_votes
+                .iter()
+                .map(|votes| vec![T::default(); votes.len()])
+                .collect(),
+            chunk_size,
+            chunk_sums: vec![vec![Sums::default(); users]; articles.div_ceil(chunk_size)],
+            user_sums: vec![Sums::default(); users],
+            article_sums: vec![Sums::default(); articles],
+            square_errors: vec![0.0; articles],
+        }
+    }
+
+    /// Prepares training the given factor, starting from the `user_values`, which include the
+    /// implicit sums of SVD++, the `article_factors` and the `biases`.
+    pub(crate) fn start_factor(
+        &mut self,
+        factor: usize,
+        user_values: &DMatrix<f64>,
+        article_factors: &DMatrix<f64>,
+        biases: &Biases,
+    ) {
+        self.users.copy_from(user_values);
+        self.articles.copy_from(article_factors);
+        // leaves the trained factor out of the dot products, so the scores do not depend on its
+        // current values, which may come from a checkpoint in the middle of the factor
+        self.users.clear_column(factor);
+        self.set_column(
+            &user_values.column(factor).into_owned(),
+            &article_factors.column(factor).into_owned(),
+        );
+        self.set_biases(biases);
+
+        let (users, articles) = (&self.users, &self.articles);
+        self.votes
+            .par_iter()
+            .zip(self.scores.par_iter_mut())
+            .enumerate()
+            .for_each(|(article_id, (votes, scores))| {
+                let article = articles.row(article_id);
+                with_avx2(|| {
+                    for (&(user_id, _), score) in votes.iter().zip(scores) {
+                        *score = dot(users.row(user_id as usize), article);
+                    }
+                });
+            });
+    }
+
+    /// Copies the factor being trained of every user and article after it has been updated.
+    pub(crate) fn set_column(
+        &mut self,
+        user_values: &DVector<f64>,
+        article_factors: &DVector<f64>,
+    ) {
+        copy(&mut self.user_values, user_values, |value| &mut value.0);
+        copy(&mut self.article_values, article_factors, |value| {
+            &mut value.0
+        });
+    }
+
+    /// Copies the biases after they have been updated.
+    pub(crate) fn set_biases(&mut self, biases: &Biases) {
+        self.global_mean = T::from_f64(biases.global_mean);
+        copy(&mut self.user_values, &biases.user, |value| &mut value.1);
+        copy(&mut self.article_values, &biases.article, |value| {
+            &mut value.1
+        });
+    }
+
+    /// Computes the gradients of the factor being trained and of the biases, summed over all
+    /// votes and with every vote weighted by the weight of its article. Returns the weighted
+    /// square error of the predicted votes.
+    pub(crate) fn compute_gradients(&mut self, weights: &[f64], loss: Loss) -> f64 {
+        let Self {
+            global_mean,
+            user_values,
+            article_values,
+            votes,
+            scores,
+            chunk_size,
+            chunk_sums,
+            user_sums,
+            article_sums,
+            square_errors,
+            ..
+        } = self;
+        let (global_mean, user_values) = (*global_mean, &*user_values);
+
+        votes
+            .par_chunks(*chunk_size)
+            .zip(scores.par_chunks(*chunk_size))
+            .zip(article_values.par_chunks(*chunk_size))
+            .zip(article_sums.par_chunks_mut(*chunk_size))
+            .zip(square_errors.par_chunks_mut(*chunk_size))
+            .zip(chunk_sums.par_iter_mut())
+            .enumerate()
+            .for_each(
+                |(chunk, (((((votes, scores), articles), sums), square_errors), users))| {
+                    users.fill(Sums::default());
+                    let first_article = chunk * *chunk_size;
+                    with_avx2(|| {
+                        for (index, (votes, scores)) in votes.iter().zip(scores).enumerate() {
+                            let article = articles[index];
+                            let weight = weights[first_article + index];
+                            let (mut article_sums, mut square_error) = (Sums::default(), 0.0);
+                            for (&(user_id, vote), &score) in votes.iter().zip(scores) {
+                                let user = user_values[user_id as usize];
+                                let score =
+                                    (score + user.0 * article.0 + global_mean + user.1 + article.1)
+                                        .to_f64();
+                                let vote = vote.to_f64();
+                                let error = T::from_f64(weight * loss.error(vote, score));
+                                square_error += weight * (vote - loss.vote(score)).powi(2);
+                                article_sums.factor += user.0 * error;
+                                article_sums.bias += error;
+                                let user_sums = &mut users[user_id as usize];
+                                user_sums.factor += article.0 * error;
+                                user_sums.bias += error;
+                            }
+                            sums[index] = article_sums;
+                            square_errors[index] = square_error;
+                        }
+                    });
+                },
+            );
+
+        let chunk_sums = &*chunk_sums;
+        user_sums
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(user_id, sums)| {
+                *sums = Sums::default();
+                for chunk in chunk_sums {
+                    sums.factor += chunk[user_id].factor;
+                    sums.bias += chunk[user_id].bias;
+                }
+            });
+
+        // summed in order, so the result does not depend on the threads
+        square_errors.iter().sum()
+    }
+
+    /// The gradient of every user's factor from the last [`Kernel::compute_gradients`].
+    pub(crate) fn user_gradient(&self) -> DVector<f64> {
+        factor_gradient(&self.user_sums)
+    }
+
+    /// The gradient of every article's factor from the last [`Kernel::compute_gradients`].
+    pub(crate) fn article_gradient(&self) -> DVector<f64> {
+        factor_gradient(&self.article_sums)
+    }
+
+    /// The gradient of every user's bias from the last [`Kernel::compute_gradients`].
+    pub(crate) fn user_bias_gradient(&self) -> DVector<f64> {
+        bias_gradient(&self.user_sums)
+    }
+
+    /// The gradient of every article's bias from the last [`Kernel::compute_gradients`].
+    pub(crate) fn article_bias_gradient(&self) -> DVector<f64> {
+        bias_gradient(&self.article_sums)
+    }
+}
+
+/// Overwrites the `field` of every value in `target` with the values of `source`, rounded to `T`.
+fn copy<T: Scalar>(
+    target: &mut Values<T>,
+    source: &DVector<f64>,
+    field: impl Fn(&mut (T, T)) -> &mut T,
+) {
+    for (target, &source) in target.iter_mut().zip(source.iter()) {
+        *field(target) = T::from_f64(source);
+    }
+}
+
+/// Collects the factor gradients of the `sums` into a vector.
+fn factor_gradient<T: Scalar>(sums: &[Sums<T>]) -> DVector<f64> {
+    DVector::from_iterator(sums.len(), sums.iter().map(|sums| sums.factor.to_f64()))
+}
+
+/// Collects the bias gradients of the `sums` into a vector.
+fn bias_gradient<T: Scalar>(sums: &[Sums<T>]) -> DVector<f64> {
+    DVector::from_iterator(sums.len(), sums.iter().map(|sums| sums.bias.to_f64()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const USERS: usize = 20;
+    const ARTICLES: usize = 150;
+    const FACTORS: usize = 12;
+    const TRAINED: usize = 3;
+
+    /// Random votes, factors, biases and weights.
+    struct Inputs {
+        article_votes: Vec<Vec<(usize, bool)>>,
+        users: DMatrix<f64>,
+        articles: DMatrix<f64>,
+        biases: Biases,
+        weights: Vec<f64>,
+    }
+
+    fn inputs() -> Inputs {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut article_votes = vec![Vec::new(); ARTICLES];
+        for votes in &mut article_votes {
+            for user_id in 0..USERS {
+                if rng.gen_bool(0.3) {
+                    votes.push((user_id, rng.gen_bool(0.7)));
+                }
+            }
+        }
+        Inputs {
+            article_votes,
+            users: DMatrix::from_fn(USERS, FACTORS, |_, _| rng.gen_range(-0.5..0.5)),
+            articles: DMatrix::from_fn(ARTICLES, FACTORS, |_, _| rng.gen_range(-0.5..0.5)),
+            biases: Biases {
+                global_mean: 0.2,
+                user: DVector::from_fn(USERS, |_, _| rng.gen_range(-0.2..0.2)),
+                article: DVector::from_fn(ARTICLES, |_, _| rng.gen_range(-0.2..0.2)),
+            },
+            weights: (0..ARTICLES).map(|_| rng.gen_range(0.5..2.0)).collect(),
+        }
+    }
+
+    /// The gradients of the trained factor and the biases, summed up over every vote with the
+    /// full dot product of the factors, as before the kernel.
+    fn reference(loss: Loss) -> [DVector<f64>; 4] {
+        let Inputs {
+            article_votes,
+            users,
+            articles,
+            biases,
+            weights,
+        } = inputs();
+        let mut gradients = [
+            DVector::zeros(USERS),
+            DVector::zeros(ARTICLES),
+            DVector::zeros(USERS),
+            DVector::zeros(ARTICLES),
+        ];
+        for (article_id, votes) in article_votes.iter().enumerate() {
+            for &(user_id, vote) in votes {
+                let score = users.row(user_id).dot(&articles.row(article_id))
+                    + biases.global_mean
+                    + biases.user[user_id]
+                    + biases.article[article_id];
+                let vote = if vote { 1.0 } else { -1.0 };
+                let error = weights[article_id] * loss.error(vote, score);
+                gradients[0][user_id] += articles[(article_id, TRAINED)] * error;
+                gradients[1][article_id] += users[(user_id, TRAINED)] * error;
+                gradients[2][user_id] += error;
+                gradients[3][article_id] += error;
+            }
+        }
+        gradients
+    }
+
+    fn kernel<T: Scalar>(loss: Loss) -> [DVector<f64>; 4] {
+        let inputs = inputs();
+        let mut kernel = Kernel::<T>::new(&inputs.article_votes, USERS);
+        kernel.start_factor(TRAINED, &inputs.users, &inputs.articles, &inputs.biases);
+        kernel.compute_gradients(&inputs.weights, loss);
+        [
+            kernel.user_gradient(),
+            kernel.article_gradient(),
+            kernel.user_bias_gradient(),
+            kernel.article_bias_gradient(),
+        ]
+    }
+
+    fn assert_close(actual: &[DVector<f64>; 4], expected: &[DVector<f64>; 4], tolerance: f64) {
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!(
+                (actual - expected).amax() < tolerance,
+                "{} differs from {}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn double_precision_matches_the_reference() {
+        for loss in [Loss::Squared, Loss::Logistic] {
+            assert_close(&kernel::<f64>(loss), &reference(loss), 1e-10);
+        }
+    }
+
+    #[test]
+    fn single_precision_matches_the_reference() {
+        for loss in [Loss::Squared, Loss::Logistic] {
+            assert_close(&kernel::<f32>(loss), &reference(loss), 1e-4);
+        }
+    }
+}
@@ -0,0 +1,517 @@
+use crate::database::{Database, Similarity, TrainingParameters};
+use crate::evaluation::Split;
+use crate::recommender::{ModelRef, Recommender};
+use crate::report::TrainingLog;
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// How strongly predictions with little support from neighbors are pulled towards the mean vote
+/// of the user. It is added to the sum of the similarities of the neighbors a user has voted on,
+/// so a single weakly similar neighbor does not decide the prediction on its own.
+const SHRINKAGE: f64 = 1.0;
+
+/// The number of shared votes from which on the similarity of two users is fully trusted. The
+/// similarity of users with fewer shared votes is scaled down proportionally (significance
+/// weighting after Herlocker et al.), since a few agreeing votes are easily a coincidence.
+const SIGNIFICANCE: usize = 50;
+
+/// The number of similar users listed when explaining the predictions of a user neighborhood.
+const SIMILAR_USERS: usize = 5;
+
+/// Whether a neighborhood model compares articles or users.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+enum NeighborhoodKind {
+    /// The neighbors of an article are the most similar articles, and a vote is predicted from
+    /// the user's votes on them.
+    #[default]
+    Articles,
+
+    /// The neighbors of a user are the most similar users, and a vote is predicted from their
+    /// votes on the article.
+    Users,
+}
+
+/// A k-nearest-neighbor model: the most similar articles of every article or the most similar
+/// users of every user, which predict a user's vote for an article from the votes on the
+/// neighbors.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct NeighborhoodModel {
+    /// The database the model was trained on, shared with the other members of an ensemble.
+    pub(crate) database: Arc<Database>,
+
+    #[serde(default)]
+    kind: NeighborhoodKind,
+
+    /// The neighbors of each article or user with their similarity, indexed by article or user id
+    /// and sorted by descending similarity.
+    neighbors: Vec<Vec<(usize, f64)>>,
+
+    /// The mean vote of each user in the training data, indexed by user id. Users without votes
+    /// get the global mean vote.
+    user_means: Vec<f64>,
+
+    #[serde(default)]
+    held_out: Split,
+
+    /// All votes of each user, sorted by article id. They are derived from the database and
+    /// therefore not stored.
+    #[serde(skip)]
+    user_votes: Vec<Vec<(usize, bool)>>,
+
+    /// The ids of the articles each user voted on, sorted. They are derived from the database and
+    /// therefore not stored.
+    #[serde(skip)]
+    voted_articles: Vec<Vec<usize>>,
+}
+
+impl NeighborhoodModel {
+    /// Creates a model from the neighbors, deriving the votes of all users from the database.
+    fn new(
+        database: Database,
+        kind: NeighborhoodKind,
+        neighbors: Vec<Vec<(usize, f64)>>,
+        user_means: Vec<f64>,
+        held_out: Split,
+    ) -> Self {
+        let mut model = Self {
+            database: Arc::new(database),
+            kind,
+            neighbors,
+            user_means,
+            held_out,
+            user_votes: Vec::new(),
+            voted_articles: Vec::new(),
+        };
+        model.aggregate();
+        model
+    }
+
+    /// Recomputes the votes of all users from the database the model was trained on.
+    pub(crate) fn aggregate(&mut self) {
+        self.user_votes = self.database.votes_by_user();
+        self.voted_articles = self
+            .user_votes
+            .iter()
+            .map(|votes| votes.iter().map(|&(article_id, _)| article_id).collect())
+            .collect();
+    }
+}
+
+impl Recommender for NeighborhoodModel {
+    fn database(&self) -> &Database {
+        &self.database
+    }
+
+    fn held_out(&self) -> &Split {
+        &self.held_out
+    }
+
+    fn voted_articles(&self, user_id: usize) -> &[usize] {
+        &self.voted_articles[user_id]
+    }
+
+    /// Predicts the vote of a user for an article as the user's mean vote plus the similarity
+    /// weighted mean deviation of the votes on the neighbors: the user's votes on the article's
+    /// neighbors, or the neighbors' votes on the article, each relative to the voter's mean vote.
+    fn predict(&self, user_id: usize, article_id: usize) -> f64 {
+        let vote = |user_id: usize, article_id: usize| {
+            let votes = &self.user_votes[user_id];
+            votes
+                .binary_search_by_key(&article_id, |&(id, _)| id)
+                .ok()
+                .map(|index| if votes[index].1 { 1.0 } else { -1.0 })
+        };
+
+        let (mut deviation, mut weight) = (0.0, 0.0);
+        let neighbors = match self.kind {
+            NeighborhoodKind::Articles => &self.neighbors[article_id],
+            NeighborhoodKind::Users => &self.neighbors[user_id],
+        };
+        for &(neighbor_id, similarity) in neighbors {
+            let (voter_id, vote) = match self.kind {
+                NeighborhoodKind::Articles => (user_id, vote(user_id, neighbor_id)),
+                NeighborhoodKind::Users => (neighbor_id, vote(neighbor_id, article_id)),
+            };
+            if let Some(vote) = vote {
+                deviation += similarity * (vote - self.user_means[voter_id]);
+                weight += similarity;
+            }
+        }
+        self.user_means[user_id] + deviation / (weight + SHRINKAGE)
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        serde_cbor::to_vec(&ModelRef::Neighborhood(self)).unwrap()
+    }
+
+    /// Lists the most similar users of the user, which only user neighborhoods know.
+    fn explain(&self, user_id: usize) -> Option<String> {
+        if self.kind != NeighborhoodKind::Users || self.neighbors[user_id].is_empty() {
+            return None;
+        }
+        let names = self.database.user_names();
+        let similar = self.neighbors[user_id]
+            .iter()
+            .take(SIMILAR_USERS)
+            .map(|&(other_id, similarity)| {
+                format!("{} (similarity {:.2})", names[other_id], similarity)
+            })
+            .collect::<Vec<_>>();
+        Some(format!(
+            "Voters with a similar taste: {}",
+            similar.join(", ")
+        ))
+    }
+}
+
+/// Trains an item-based k-nearest-neighbor model after Sarwar et al. The similarity of every
+/// pair of articles is computed from the votes of the users who voted on both, and the most
+/// similar `parameters.neighbors` articles with a positive similarity are kept per article. The
+/// articles are processed in parallel. Unlike the factor models, predictions can be explained by
+/// the neighbors the user voted on.
+pub(crate) fn train_items(
+    database: Database,
+    parameters: &TrainingParameters,
+    held_out: Split,
+    log: &TrainingLog,
+) -> NeighborhoodModel {
+    let TrainingParameters {
+        similarity,
+        neighbors,
+        ..
+    } = *parameters;
+
+    let now = std::time::Instant::now();
+    let user_votes = database.votes_by_user();
+    let user_means = user_means(&database, &user_votes);
+
+    // The value a vote contributes to the similarities. Jaccard only counts upvoters, adjusted
+    // cosine removes the tendency of the user to upvote.
+    let value = |user_id: usize, vote: bool| {
+        let vote = if vote { 1.0 } else { -1.0 };
+        match similarity {
+            Similarity::AdjustedCosine => vote - user_means[user_id],
+            Similarity::Jaccard => (vote + 1.0) / 2.0,
+            _ => vote,
+        }
+    };
+
+    // the length of each article's vector of votes, or its number of upvoters for Jaccard
+    let norms = database
+        .article_votes()
+        .iter()
+        .map(|votes| {
+            let sum = votes
+                .iter()
+                .map(|&(user_id, vote)| value(user_id, vote).powi(2))
+                .sum::<f64>();
+            match similarity {
+                Similarity::Jaccard => sum,
+                _ => sum.sqrt(),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if log.verbose {
+        println!(
+            "Computing the similarities of {} articles...",
+            database.article_count()
+        );
+    }
+    let neighbors = database
+        .article_votes()
+        .par_iter()
+        .enumerate()
+        .map(|(article_id, votes)| {
+            // the dot products with all other articles, summed over the common voters
+            let mut products = vec![0.0; norms.len()];
+            for &(user_id, vote) in votes {
+                let own = value(user_id, vote);
+                if own == 0.0 {
+                    continue;
+                }
+                for &(other_id, other_vote) in &user_votes[user_id] {
+                    products[other_id] += own * value(user_id, other_vote);
+                }
+            }
+
+            let candidates = products
+                .iter()
+                .enumerate()
+                .filter(|&(other_id, &product)| other_id != article_id && product > 0.0)
+                .map(|(other_id, &product)| {
+                    let similarity = match similarity {
+                        Similarity::Jaccard => {
+                            product / (norms[article_id] + norms[other_id] - product)
+                        }
+                        _ => product / (norms[article_id] * norms[other_id]),
+                    };
+                    (other_id, similarity)
+                })
+                .collect::<Vec<_>>();
+
+            most_similar(candidates, neighbors)
+        })
+        .collect::<Vec<_>>();
+
+    if log.verbose {
+        let kept = neighbors.iter().map(Vec::len).sum::<usize>();
+        println!(
+            "Kept {:.1} neighbors per article on average.",
+            kept as f64 / neighbors.len().max(1) as f64
+        );
+        println!("Training finished in {}ms.", now.elapsed().as_millis());
+    }
+
+    NeighborhoodModel::new(
+        database,
+        NeighborhoodKind::Articles,
+        neighbors,
+        user_means,
+        held_out,
+    )
+}
+
+/// Trains a user-based k-nearest-neighbor model. The similarity of two users is computed from
+/// the articles both voted on, as the `cosine` of their votes, the `pearson` correlation of the
+/// votes relative to each user's mean vote or the `jaccard` index of their upvotes, and is scaled
+/// down for users with few shared votes. The most similar `parameters.neighbors` users with a
+/// positive similarity are kept per user, so every recommendation is what similar voters liked.
+/// The users are processed in parallel.
+pub(crate) fn train_users(
+    database: Database,
+    parameters: &TrainingParameters,
+    held_out: Split,
+    log: &TrainingLog,
+) -> NeighborhoodModel {
+    let TrainingParameters {
+        similarity,
+        neighbors,
+        ..
+    } = *parameters;
+
+    let now = std::time::Instant::now();
+    let user_votes = database.votes_by_user();
+    let user_means = user_means(&database, &user_votes);
+    let users = user_votes.len();
+
+    // the value a vote contributes to the similarities, which only counts upvotes for Jaccard
+    let value = |user_id: usize, vote: bool| {
+        let vote = if vote { 1.0 } else { -1.0 };
+        match similarity {
+            Similarity::Pearson => vote - user_means[user_id],
+            Similarity::Jaccard => (vote + 1.0) / 2.0,
+            _ => vote,
+        }
+    };
+    let upvotes = user_votes
+        .iter()
+        .map(|votes| votes.iter().filter(|&&(_, vote)| vote).count() as f64)
+        .collect::<Vec<_>>();
+
+    if log.verbose {
+        println!("Computing the similarities of {} users...", users);
+    }
+    let neighbors = user_votes
+        .par_iter()
+        .enumerate()
+        .map_init(
+            || (vec![Shared::default(); users], Vec::new()),
+            |(shared, touched), (user_id, votes)| {
+                // the sums over the articles both users voted on, reset for every user
+                for &(article_id, vote) in votes {
+                    let own = value(user_id, vote);
+                    for &(other_id, other_vote) in &database.article_votes()[article_id] {
+                        let other = value(other_id, other_vote);
+                        let sums = &mut shared[other_id];
+                        if sums.votes == 0 {
+                            touched.push(other_id);
+                        }
+                        sums.product += own * other;
+                        sums.own_square += own * own;
+                        sums.other_square += other * other;
+                        sums.votes += 1;
+                    }
+                }
+
+                let mut candidates = Vec::new();
+                for &other_id in touched.iter() {
+                    let sums = std::mem::take(&mut shared[other_id]);
+                    if other_id == user_id || sums.product <= 0.0 {
+                        continue;
+                    }
+                    let similarity = match similarity {
+                        Similarity::Jaccard => {
+                            sums.product / (upvotes[user_id] + upvotes[other_id] - sums.product)
+                        }
+                        _ => sums.product / (sums.own_square * sums.other_square).sqrt(),
+                    };
+                    let significance = sums.votes.min(SIGNIFICANCE) as f64 / SIGNIFICANCE as f64;
+                    candidates.push((other_id, similarity * significance));
+                }
+                touched.clear();
+                most_similar(candidates, neighbors)
+            },
+        )
+        .collect::<Vec<_>>();
+
+    if log.verbose {
+        let kept = neighbors.iter().map(Vec::len).sum::<usize>();
+        println!(
+            "Kept {:.1} neighbors per user on average.",
+            kept as f64 / neighbors.len().max(1) as f64
+        );
+        println!("Training finished in {}ms.", now.elapsed().as_millis());
+    }
+
+    NeighborhoodModel::new(
+        database,
+        NeighborhoodKind::Users,
+        neighbors,
+        user_means,
+        held_out,
+    )
+}
+
+/// The sums over the shared votes of a pair of users, from which their similarity is computed.
+#[derive(Clone, Copy, Debug, Default)]
+struct Shared {
+    product: f64,
+    own_square: f64,
+    other_square: f64,
+    votes: usize,
+}
+
+/// The mean vote of each user in `user_votes`, indexed by user id. Users without votes get the
+/// global mean vote of the database.
+fn user_means(database: &Database, user_votes: &[Vec<(usize, bool)>]) -> Vec<f64> {
+    let global_mean = database.mean_vote().unwrap_or(0.0);
+    user_votes
+        .iter()
+        .map(|votes| {
+            if votes.is_empty() {
+                return global_mean;
+            }
+            let upvotes = votes.iter().filter(|&&(_, vote)| vote).count();
+            (2 * upvotes) as f64 / votes.len() as f64 - 1.0
+        })
+        .collect()
+}
+
+/// Keeps the `count` candidates with the highest similarity, most similar first.
+fn most_similar(mut candidates: Vec<(usize, f64)>, count: usize) -> Vec<(usize, f64)> {
+    let by_similarity = |a: &(usize, f64), b: &(usize, f64)| b.1.total_cmp(&a.1);
+    if candidates.len() > count {
+        candidates.select_nth_unstable_by(count, by_similarity);
+        candidates.truncate(count);
+    }
+    candidates.sort_by(by_similarity);
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::ModelKind;
+
+    /// Three users voting on three articles:
+    ///
+    /// | article | user 0 | user 1 | user 2 |
+    /// |---------|--------|--------|--------|
+    /// | 0       | up     | up     | down   |
+    /// | 1       | up     | up     | up     |
+    /// | 2       | down   | up     |        |
+    fn database() -> Database {
+        let mut database = Database::new();
+        for user in ["a", "b", "c"] {
+            database.add_user(user.to_string());
+        }
+        let votes = [
+            vec![(0, true), (1, true), (2, false)],
+            vec![(0, true), (1, true), (2, true)],
+            vec![(0, false), (1, true)],
+        ];
+        for (article_id, votes) in votes.into_iter().enumerate() {
+            database.add_article(format!("scp-{}", article_id), String::new(), votes);
+        }
+        database
+    }
+
+    fn train(model: ModelKind, similarity: Similarity) -> NeighborhoodModel {
+        let parameters = TrainingParameters {
+            similarity,
+            ..TrainingParameters::default()
+        };
+        let log = TrainingLog::default();
+        match model {
+            ModelKind::ItemKnn => train_items(database(), &parameters, Split::default(), &log),
+            _ => train_users(database(), &parameters, Split::default(), &log),
+        }
+    }
+
+    fn assert_neighbors(actual: &[(usize, f64)], expected: &[(usize, f64)]) {
+        assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+        for (&(id, similarity), &(expected_id, expected_similarity)) in actual.iter().zip(expected)
+        {
+            assert_eq!(id, expected_id);
+            assert!(
+                (similarity - expected_similarity).abs() < 1e-12,
+                "{:?}",
+                actual
+            );
+        }
+    }
+
+    #[test]
+    fn item_cosine_keeps_positive_similarities() {
+        let model = train(ModelKind::ItemKnn, Similarity::Cosine);
+        // articles 0 and 1 agree on two of three votes, article 2 is orthogonal to both
+        assert_neighbors(&model.neighbors[0], &[(1, 1.0 / 3.0)]);
+        assert_neighbors(&model.neighbors[1], &[(0, 1.0 / 3.0)]);
+        assert_neighbors(&model.neighbors[2], &[]);
+    }
+
+    #[test]
+    fn item_jaccard_compares_upvoters() {
+        let model = train(ModelKind::ItemKnn, Similarity::Jaccard);
+        assert_neighbors(&model.neighbors[0], &[(1, 2.0 / 3.0), (2, 1.0 / 2.0)]);
+        assert_neighbors(&model.neighbors[2], &[(0, 1.0 / 2.0), (1, 1.0 / 3.0)]);
+    }
+
+    #[test]
+    fn item_adjusted_cosine_removes_user_means() {
+        // relative to the mean votes 1/3, 1 and 0 of the users, all articles disagree
+        let model = train(ModelKind::ItemKnn, Similarity::AdjustedCosine);
+        assert!(model.neighbors.iter().all(Vec::is_empty));
+    }
+
+    #[test]
+    fn user_similarities_are_weighted_by_significance() {
+        let model = train(ModelKind::UserKnn, Similarity::Cosine);
+        let significance = 3.0 / SIGNIFICANCE as f64;
+        assert_neighbors(&model.neighbors[0], &[(1, 1.0 / 3.0 * significance)]);
+        assert_neighbors(&model.neighbors[2], &[]);
+
+        let model = train(ModelKind::UserKnn, Similarity::Jaccard);
+        let (three, two) = (3.0 / SIGNIFICANCE as f64, 2.0 / SIGNIFICANCE as f64);
+        assert_neighbors(
+            &model.neighbors[0],
+            &[(1, 2.0 / 3.0 * three), (2, 1.0 / 2.0 * two)],
+        );
+        assert_neighbors(
+            &model.neighbors[2],
+            &[(0, 1.0 / 2.0 * two), (1, 1.0 / 3.0 * two)],
+        );
+    }
+
+    #[test]
+    fn predict_moves_the_user_mean_towards_similar_votes() {
+        // the neighbors of article 2 are article 0, which user 2 downvoted, and article 1, which
+        // user 2 upvoted, and the mean vote of user 2 is 0
+        let model = train(ModelKind::ItemKnn, Similarity::Jaccard);
+        let (down, up) = (1.0 / 2.0, 1.0 / 3.0);
+        let expected = (down * (-1.0 - 0.0) + up * (1.0 - 0.0)) / (down + up + SHRINKAGE);
+        assert!((model.predict(2, 2) - expected).abs() < 1e-12);
+    }
+}
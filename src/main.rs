@@ -1,8 +1,38 @@
-use crate::database::{Database, PredictionModel};
+use crate::checkpoint::{Checkpoint, Checkpointing, DEFAULT_CHECKPOINT_INTERVAL};
+use crate::database::{
+    Algorithm, Database, EarlyStopping, Initialization, Loss, ModelKind, NegativeSampling,
+    Propensity, RegularizationScaling, Similarity, StoppingScope, TrainingParameters,
+};
+use crate::evaluation::{format_metric, Metrics, Split, SplitStrategy};
+use crate::kernel::Precision;
+use crate::optimizer::Optimizer;
+use crate::recommender::{Model, Recommender};
+use crate::report::{Curve, Run, TrainingLog};
+use crate::storage::{Storage, DEFAULT_BACKUPS};
+use crate::tuning::{Objective, Search, SearchSpace};
 use crate::update::Updater;
-use clap::{arg, command, value_parser, ArgAction};
+use clap::builder::RangedU64ValueParser;
+use clap::{arg, command, value_parser, ArgAction, ArgMatches};
+use std::path::PathBuf;
+use std::time::Duration;
 
+mod als;
+mod bpr;
+mod checkpoint;
 pub(crate) mod database;
+mod ensemble;
+mod evaluation;
+mod hogwild;
+mod kernel;
+mod knn;
+mod optimizer;
+mod pagerank;
+mod popularity;
+mod recommender;
+mod report;
+mod sgd;
+mod storage;
+mod tuning;
 mod update;
 
 fn main() {
@@ -11,6 +41,10 @@ fn main() {
         .subcommand_required(true)
         .arg_required_else_help(true)
         .arg(arg!(-p --threads [THREADS] "The number of threads to use for parallelism").value_parser(value_parser!(usize)))
+        .arg(arg!(-b --backups [BACKUPS] "The number of backup generations to keep when overwriting files").value_parser(value_parser!(usize)))
+        .arg(arg!(-d --"data-dir" [DATA_DIR] "The directory containing the database and model files [env: GEYSER_DATA_DIR]").value_parser(value_parser!(PathBuf)))
+        .arg(arg!(--"database-file" [DATABASE_FILE] "The database file, overriding the data directory").value_parser(value_parser!(PathBuf)))
+        .arg(arg!(--"model-file" [MODEL_FILE] "The prediction model file, overriding the data directory").value_parser(value_parser!(PathBuf)))
         .subcommand(
             command!("update")
                 .about("Update the database by downloading articles from the wiki.")
@@ -20,10 +54,75 @@ fn main() {
         .subcommand(
             command!("train")
                 .about("train the model")
+                .arg(arg!(--model [MODEL] "The kind of prediction model").value_parser(["factors", "item-knn", "user-knn", "popularity", "pagerank"]))
+                .arg(arg!(-a --algorithm [ALGORITHM] "The training algorithm of factor models").value_parser(["funk", "svdpp", "sgd", "als", "implicit-als", "bpr"]))
+                .arg(arg!(--confidence [CONFIDENCE] "How much more a vote is trusted than no vote by implicit ALS").value_parser(value_parser!(f64)))
+                .arg(arg!(--loss [LOSS] "The loss minimized by the gradient descent algorithms").value_parser(["squared", "logistic"]))
+                .arg(arg!(--optimizer [OPTIMIZER] "How the gradient-based algorithms adapt the learning rate of every parameter").value_parser(["plain", "adagrad", "rmsprop", "adam"]))
+                .arg(arg!(--precision [PRECISION] "The floating point precision the funk and svdpp algorithms compute the gradients in").value_parser(["f32", "f64"]))
+                .arg(arg!(--propensity [PROPENSITY] "Weight votes by the inverse propensity of their article, estimated from this").value_parser(["uniform", "popularity", "age", "series"]))
+                .arg(arg!(--similarity [SIMILARITY] "How the kNN models compute the similarity of two articles or users").value_parser(["cosine", "adjusted-cosine", "pearson", "jaccard"]))
+                .arg(arg!(--neighbors [NEIGHBORS] "The number of most similar articles or users the kNN models keep").value_parser(value_parser!(usize)))
+                .arg(arg!(--restart [RESTART] "The probability that the PageRank walk jumps back to its start at every step").value_parser(restart))
+                .arg(arg!(--negatives [NEGATIVES] "Which articles BPR ranks below the upvoted ones").value_parser(["any", "unvoted", "downvoted"]))
+                .arg(arg!(--init [INIT] "How the factors are initialized").value_parser(["uniform", "normal", "constant"]))
+                .arg(arg!(--init_scale [INIT_SCALE] "The range, standard deviation or value of the initial factors").value_parser(scale).requires("init"))
+                .arg(arg!(--deterministic "Produce bit-identical models for the same seed, at the cost of some parallelism"))
                 .arg(arg!(-l --latent_factors [LATENT_FACTORS] "The number of latent factors to use for the model").value_parser(value_parser!(usize)))
                 .arg(arg!(-i --iterations [ITERATIONS] "The number of iterations to train the model").value_parser(value_parser!(usize)))
                 .arg(arg!(-r --learning_rate [LEARNING_RATE] "The learning rate to use for the model").value_parser(value_parser!(f64)))
                 .arg(arg!(-o --regularization [REGULARIZATION] "The regularization to use for the model").value_parser(value_parser!(f64)))
+                .arg(arg!(--user_regularization [REGULARIZATION] "The regularization of the user factors, if different").value_parser(value_parser!(f64)))
+                .arg(arg!(--article_regularization [REGULARIZATION] "The regularization of the article factors, if different").value_parser(value_parser!(f64)))
+                .arg(arg!(--bias_regularization [REGULARIZATION] "The regularization of the biases, if different from the factors (0 to disable)").value_parser(value_parser!(f64)))
+                .arg(arg!(--regularization_scaling [SCALING] "How the regularization grows with the number of votes of a user or article").value_parser(["weighted-lambda", "constant", "frequency"]))
+                .arg(arg!(--frequency_exponent [EXPONENT] "The power of the number of votes the frequency scaling multiplies the regularization by").value_parser(value_parser!(f64)))
+                .arg(arg!(-s --split [SPLIT] "Hold out votes from training for validation and testing").value_parser(["random", "leave-k-out", "article-order"]))
+                .arg(arg!(--test_fraction [TEST_FRACTION] "The fraction of votes (or articles) held out for testing").value_parser(fraction))
+                .arg(arg!(--validation_fraction [VALIDATION_FRACTION] "The fraction of votes (or articles) held out for validation").value_parser(fraction))
+                .arg(arg!(--leave_out [LEAVE_OUT] "The number of votes per user held out for testing with leave-k-out").value_parser(value_parser!(usize)))
+                .arg(arg!(--leave_out_validation [LEAVE_OUT_VALIDATION] "The number of votes per user held out for validation with leave-k-out").value_parser(value_parser!(usize)))
+                .arg(arg!(--seed [SEED] "The seed for random decisions during training").value_parser(value_parser!(u64)))
+                .arg(arg!(--biases "Learn a global mean and user and article biases in addition to the factors"))
+                .arg(arg!(-e --early_stopping [PATIENCE] "Stop when the validation error has not improved for this many iterations").value_parser(value_parser!(usize)))
+                .arg(arg!(--stop [STOP] "Whether early stopping ends only the current factor or the whole training").value_parser(["factor", "training"]))
+                .arg(arg!(--select_rank [PATIENCE] "Stop adding factors when the validation error has not improved for this many factors, and keep the best number").value_parser(value_parser!(usize)))
+                .arg(arg!(--checkpoint "Regularly save the training progress, so an interrupted training can be resumed"))
+                .arg(arg!(--checkpoint_interval [SECONDS] "The minimum time between two checkpoints in seconds").value_parser(value_parser!(u64)))
+                .arg(arg!(--resume "Resume the training from the last checkpoint, with the parameters it was started with"))
+                .arg(arg!(--log [LOG] "Write the losses and timings of every iteration to this file as JSON Lines").value_parser(value_parser!(PathBuf)))
+                .arg(arg!(--blend [MEMBERS] "Train a linear blend of these algorithms and models instead of a single model").value_parser(["funk", "svdpp", "sgd", "als", "implicit-als", "bpr", "item-knn", "user-knn", "popularity"]).action(ArgAction::Append).value_delimiter(','))
+                .arg(arg!(--probe_fraction [PROBE_FRACTION] "The fraction of training votes the blend weights are fitted on").value_parser(value_parser!(f64)))
+        )
+        .subcommand(
+            command!("tune")
+                .about("search for the best training parameters with cross-validation and train the best model")
+                .arg(arg!(-s --search [SEARCH] "How candidate parameters are chosen").value_parser(["grid", "random"]))
+                .arg(arg!(-n --trials [TRIALS] "The number of candidates sampled by random search").value_parser(value_parser!(usize)))
+                .arg(arg!(-k --folds [FOLDS] "The number of cross-validation folds").value_parser(value_parser!(usize)))
+                .arg(arg!(-m --metric [METRIC] "The validation metric to optimize").value_parser(["rmse", "mae", "auc", "precision", "recall", "map", "ndcg", "debiased-rmse", "debiased-recall"]))
+                .arg(arg!(--cutoff [CUTOFF] "The number of top articles per user considered by the ranking metrics").value_parser(RangedU64ValueParser::<usize>::new().range(1..)))
+                .arg(arg!(-l --latent_factors [LATENT_FACTORS] "The numbers of latent factors to try").value_parser(value_parser!(usize)).action(ArgAction::Append).value_delimiter(','))
+                .arg(arg!(-i --iterations [ITERATIONS] "The numbers of iterations to try").value_parser(value_parser!(usize)).action(ArgAction::Append).value_delimiter(','))
+                .arg(arg!(-r --learning_rate [LEARNING_RATE] "The learning rates to try").value_parser(positive).action(ArgAction::Append).value_delimiter(','))
+                .arg(arg!(-o --regularization [REGULARIZATION] "The regularizations to try").value_parser(positive).action(ArgAction::Append).value_delimiter(','))
+                .arg(arg!(--model [MODEL] "The kind of prediction model").value_parser(["factors", "item-knn", "user-knn", "popularity", "pagerank"]))
+                .arg(arg!(-a --algorithm [ALGORITHM] "The training algorithm of factor models").value_parser(["funk", "svdpp", "sgd", "als", "implicit-als", "bpr"]))
+                .arg(arg!(--confidence [CONFIDENCE] "How much more a vote is trusted than no vote by implicit ALS").value_parser(value_parser!(f64)))
+                .arg(arg!(--loss [LOSS] "The loss minimized by the gradient descent algorithms").value_parser(["squared", "logistic"]))
+                .arg(arg!(--optimizer [OPTIMIZER] "How the gradient-based algorithms adapt the learning rate of every parameter").value_parser(["plain", "adagrad", "rmsprop", "adam"]))
+                .arg(arg!(--precision [PRECISION] "The floating point precision the funk and svdpp algorithms compute the gradients in").value_parser(["f32", "f64"]))
+                .arg(arg!(--propensity [PROPENSITY] "Weight votes by the inverse propensity of their article, estimated from this").value_parser(["uniform", "popularity", "age", "series"]))
+                .arg(arg!(--similarity [SIMILARITY] "How the kNN models compute the similarity of two articles or users").value_parser(["cosine", "adjusted-cosine", "pearson", "jaccard"]))
+                .arg(arg!(--neighbors [NEIGHBORS] "The number of most similar articles or users the kNN models keep").value_parser(value_parser!(usize)))
+                .arg(arg!(--restart [RESTART] "The probability that the PageRank walk jumps back to its start at every step").value_parser(restart))
+                .arg(arg!(--negatives [NEGATIVES] "Which articles BPR ranks below the upvoted ones").value_parser(["any", "unvoted", "downvoted"]))
+                .arg(arg!(--init [INIT] "How the factors are initialized").value_parser(["uniform", "normal", "constant"]))
+                .arg(arg!(--init_scale [INIT_SCALE] "The range, standard deviation or value of the initial factors").value_parser(scale).requires("init"))
+                .arg(arg!(--deterministic "Produce bit-identical models for the same seed, at the cost of some parallelism"))
+                .arg(arg!(--biases "Learn a global mean and user and article biases in addition to the factors"))
+                .arg(arg!(--seed [SEED] "The seed for the fold assignment and random search").value_parser(value_parser!(u64)))
+                .arg(arg!(--results [RESULTS] "The file the results table is written to").value_parser(value_parser!(PathBuf)))
         )
         .subcommand(
             command!("predict")
@@ -37,6 +136,47 @@ fn main() {
                 .arg(arg!(-t --top [TOP] "The number of top users to predict").value_parser(value_parser!(usize)))
                 .arg(arg!([ARTICLES]).action(ArgAction::Append))
         )
+        .subcommand(
+            command!("evaluate")
+                .about("evaluate the model on the votes held out from training")
+                .arg(arg!(-k --cutoff [CUTOFF] "The number of top articles per user considered by the ranking metrics").value_parser(RangedU64ValueParser::<usize>::new().range(1..)))
+                .arg(arg!(--validation "Evaluate on the validation votes instead of the test votes"))
+                .arg(arg!(--propensity [PROPENSITY] "The propensity estimate the debiased metrics weight votes with").value_parser(["popularity", "age", "series"]))
+        )
+        .subcommand(
+            command!("benchmark")
+                .about("compare the training time and accuracy of algorithms on the same held out votes")
+                .arg(arg!(-a --algorithm [ALGORITHM] "The training algorithms or other models to compare").value_parser(["funk", "svdpp", "sgd", "als", "implicit-als", "bpr", "item-knn", "user-knn", "popularity", "pagerank"]).action(ArgAction::Append).value_delimiter(','))
+                .arg(arg!(-l --latent_factors [LATENT_FACTORS] "The number of latent factors to use for the models").value_parser(value_parser!(usize)))
+                .arg(arg!(-i --iterations [ITERATIONS] "The number of iterations for each algorithm").value_parser(value_parser!(usize)).action(ArgAction::Append).value_delimiter(','))
+                .arg(arg!(-r --learning_rate [LEARNING_RATE] "The learning rate for each algorithm").value_parser(value_parser!(f64)).action(ArgAction::Append).value_delimiter(','))
+                .arg(arg!(-o --regularization [REGULARIZATION] "The regularization to use for the models").value_parser(value_parser!(f64)))
+                .arg(arg!(--biases "Learn a global mean and user and article biases in addition to the factors"))
+                .arg(arg!(--loss [LOSS] "The loss minimized by the gradient descent algorithms").value_parser(["squared", "logistic"]))
+                .arg(arg!(--optimizer [OPTIMIZER] "How the gradient-based algorithms adapt the learning rate of every parameter").value_parser(["plain", "adagrad", "rmsprop", "adam"]))
+                .arg(arg!(--precision [PRECISION] "The floating point precisions the funk and svdpp algorithms compute the gradients in, each trained separately").value_parser(["f32", "f64"]).action(ArgAction::Append).value_delimiter(','))
+                .arg(arg!(--propensity [PROPENSITY] "Weight votes by the inverse propensity of their article, estimated from this").value_parser(["uniform", "popularity", "age", "series"]))
+                .arg(arg!(--similarity [SIMILARITY] "How the kNN models compute the similarity of two articles or users").value_parser(["cosine", "adjusted-cosine", "pearson", "jaccard"]))
+                .arg(arg!(--neighbors [NEIGHBORS] "The number of most similar articles or users the kNN models keep").value_parser(value_parser!(usize)))
+                .arg(arg!(--restart [RESTART] "The probability that the PageRank walk jumps back to its start at every step").value_parser(restart))
+                .arg(arg!(--test_fraction [TEST_FRACTION] "The fraction of votes held out for testing").value_parser(fraction))
+                .arg(arg!(-k --cutoff [CUTOFF] "The number of top articles per user considered by the ranking metrics").value_parser(RangedU64ValueParser::<usize>::new().range(1..)))
+                .arg(arg!(--seed [SEED] "The seed for the held out votes and random decisions during training").value_parser(value_parser!(u64)))
+        )
+        .subcommand(
+            command!("report")
+                .about("compare the loss curves of training runs written with train --log")
+                .arg(arg!(--curve [CURVE] "Which loss to plot").value_parser(["training", "validation"]))
+                .arg(arg!(--svg [SVG] "Also write the plot as an SVG image to this file").value_parser(value_parser!(PathBuf)))
+                .arg(arg!(<LOGS> "The training logs to compare").value_parser(value_parser!(PathBuf)).action(ArgAction::Append))
+        )
+        .subcommand(
+            command!("restore")
+                .about("roll the database or prediction model back to a backup generation")
+                .arg(arg!(-g --generation [GENERATION] "The backup generation to restore, 1 being the most recent").value_parser(RangedU64ValueParser::<usize>::new().range(1..)))
+                .arg(arg!(-l --list "List the available backup generations instead of restoring"))
+                .arg(arg!([FILE] "The file to restore").value_parser(["database", "model"]))
+        )
         .get_matches();
 
     if let Some(&threads) = matches.get_one::<usize>("threads") {
@@ -47,27 +187,395 @@ fn main() {
             .unwrap();
     }
 
+    let backups = *matches
+        .get_one::<usize>("backups")
+        .unwrap_or(&DEFAULT_BACKUPS);
+    let mut storage = Storage::new(matches.get_one::<PathBuf>("data-dir").cloned(), backups);
+    if let Some(path) = matches.get_one::<PathBuf>("database-file") {
+        storage.database_path = path.clone();
+    }
+    if let Some(path) = matches.get_one::<PathBuf>("model-file") {
+        storage.model_path = path.clone();
+    }
+
     match matches.subcommand() {
         Some(("update", args)) => {
             let from = *args.get_one::<usize>("from").unwrap_or(&6000usize);
             let to = *args.get_one::<usize>("to").unwrap_or(&7999usize);
-            Updater::load().update(from, to);
+            Updater::load(storage).update(from, to);
         }
         Some(("train", args)) => {
-            let latent_factors = *args.get_one::<usize>("latent_factors").unwrap_or(&30usize);
-            let iterations = *args.get_one::<usize>("iterations").unwrap_or(&120usize);
-            let learning_rate = *args.get_one::<f64>("learning_rate").unwrap_or(&0.004f64);
-            let regularization = *args.get_one::<f64>("regularization").unwrap_or(&0.02f64);
-            let database = Database::load();
-            database.train_prediction_model(
-                latent_factors,
-                iterations,
-                learning_rate,
-                regularization,
+            // a resumed training is obviously worth saving checkpoints for
+            let checkpointing =
+                (args.get_flag("checkpoint") || args.get_flag("resume")).then(|| Checkpointing {
+                    storage: &storage,
+                    interval: Duration::from_secs(
+                        *args
+                            .get_one::<u64>("checkpoint_interval")
+                            .unwrap_or(&DEFAULT_CHECKPOINT_INTERVAL),
+                    ),
+                });
+            if args.get_flag("resume") {
+                if !storage.checkpoint_path.exists() {
+                    println!("There is no checkpoint to resume from.");
+                    return;
+                }
+                let Checkpoint {
+                    parameters,
+                    database,
+                    held_out,
+                    progress,
+                } = Checkpoint::load(&storage);
+                println!(
+                    "Resuming training at factor {}/{}, iteration {}.",
+                    progress.factor() + 1,
+                    parameters.latent_factors,
+                    progress.iteration()
+                );
+                let log = training_log(args, &parameters, true);
+                let prediction_model = database.train_gradient_descent(
+                    &parameters,
+                    held_out,
+                    &log,
+                    Some(progress),
+                    checkpointing.as_ref(),
+                );
+                prediction_model.save(&storage);
+                Checkpoint::remove(&storage);
+                println!("Saved prediction model to file.");
+                return;
+            }
+
+            let defaults = TrainingParameters::default();
+            let seed = *args.get_one::<u64>("seed").unwrap_or(&0u64);
+            let parameters = TrainingParameters {
+                model: model_kind(args),
+                algorithm: algorithm(args),
+                latent_factors: *args
+                    .get_one::<usize>("latent_factors")
+                    .unwrap_or(&defaults.latent_factors),
+                iterations: *args
+                    .get_one::<usize>("iterations")
+                    .unwrap_or(&defaults.iterations),
+                learning_rate: *args
+                    .get_one::<f64>("learning_rate")
+                    .unwrap_or(&defaults.learning_rate),
+                regularization: *args
+                    .get_one::<f64>("regularization")
+                    .unwrap_or(&defaults.regularization),
+                user_regularization: args.get_one::<f64>("user_regularization").copied(),
+                article_regularization: args.get_one::<f64>("article_regularization").copied(),
+                bias_regularization: args.get_one::<f64>("bias_regularization").copied(),
+                regularization_scaling: regularization_scaling(args),
+                biases: args.get_flag("biases"),
+                loss: loss(args),
+                confidence: *args
+                    .get_one::<f64>("confidence")
+                    .unwrap_or(&defaults.confidence),
+                negative_sampling: negative_sampling(args),
+                initialization: initialization(args),
+                propensity: propensity(args),
+                optimizer: optimizer(args),
+                precision: precision(args),
+                similarity: similarity(args),
+                neighbors: *args
+                    .get_one::<usize>("neighbors")
+                    .unwrap_or(&defaults.neighbors),
+                restart: *args.get_one::<f64>("restart").unwrap_or(&defaults.restart),
+                seed,
+                deterministic: args.get_flag("deterministic"),
+                early_stopping: args.get_one::<usize>("early_stopping").map(|&patience| {
+                    EarlyStopping {
+                        patience,
+                        scope: match args.get_one::<String>("stop").map(String::as_str) {
+                            Some("training") => StoppingScope::Training,
+                            _ => StoppingScope::Factor,
+                        },
+                    }
+                }),
+                rank_selection: args.get_one::<usize>("select_rank").copied(),
+            };
+            let strategy = split_strategy(args);
+            if let Some(
+                SplitStrategy::Random { validation, test }
+                | SplitStrategy::ArticleOrder { validation, test },
+            ) = strategy
+            {
+                if validation + test >= 1.0 {
+                    println!("The validation and test fractions must add up to less than 1.");
+                    return;
+                }
+            }
+            let database = Database::load(&storage);
+            let (database, held_out) = match strategy {
+                Some(strategy) => Split::new(&database, strategy, seed),
+                None => (database, Split::default()),
+            };
+            if parameters.early_stopping.is_some() && !held_out.has_validation() {
+                println!("Early stopping requires votes held out for validation.");
+                return;
+            }
+            if parameters.rank_selection.is_some() && !held_out.has_validation() {
+                println!("Selecting the number of factors requires votes held out for validation.");
+                return;
+            }
+            if parameters.rank_selection.is_some()
+                && parameters
+                    .early_stopping
+                    .is_some_and(|stopping| stopping.scope == StoppingScope::Training)
+            {
+                println!("Selecting the number of factors cannot be combined with --stop training, which already stops adding factors.");
+                return;
+            }
+            if parameters.rank_selection.is_some() && !parameters.incremental() {
+                println!("Selecting the number of factors is only supported by the funk and svdpp algorithms.");
+                return;
+            }
+            if parameters.early_stopping.is_some()
+                && parameters.model == ModelKind::Factors
+                && parameters.algorithm == Algorithm::Bpr
+            {
+                println!(
+                    "Early stopping is not supported by BPR, since it does not predict votes."
+                );
+                return;
+            }
+            if parameters.early_stopping.is_some() && parameters.model != ModelKind::Factors {
+                println!("Early stopping is only supported by factor models, since the other models are not trained iteratively.");
+                return;
+            }
+            if parameters.model != ModelKind::Factors
+                && !args.contains_id("blend")
+                && (args.contains_id("latent_factors") || parameters.biases)
+            {
+                println!("Latent factors and biases are only supported by factor models.");
+                return;
+            }
+            if !parameters.model.supports(parameters.similarity) {
+                println!("Item kNN supports the cosine, adjusted-cosine and jaccard similarities, user kNN the cosine, pearson and jaccard similarities.");
+                return;
+            }
+            if parameters.loss == Loss::Logistic && !parameters.gradient_descent() {
+                println!("The logistic loss is only supported by the gradient descent algorithms.");
+                return;
+            }
+            if parameters.propensity != Propensity::Uniform && !parameters.gradient_descent() {
+                println!("Inverse propensity weighting is only supported by the gradient descent algorithms.");
+                return;
+            }
+            if parameters.optimizer != Optimizer::Plain && !parameters.gradient_based() {
+                println!("The adaptive optimizers are only supported by the funk, svdpp, sgd and bpr algorithms.");
+                return;
+            }
+            if checkpointing.is_some() && !parameters.incremental() {
+                println!("Checkpoints are only supported by the funk and svdpp algorithms.");
+                return;
+            }
+            let blend = args.get_many::<String>("blend").map(|names| {
+                names
+                    .map(|name| {
+                        (
+                            name.clone(),
+                            TrainingParameters {
+                                model: parse_model_kind(name),
+                                algorithm: parse_algorithm(name),
+                                ..parameters
+                            },
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            });
+            let probe = *args.get_one::<f64>("probe_fraction").unwrap_or(&0.1f64);
+            if blend.is_some()
+                && (parameters.early_stopping.is_some()
+                    || parameters.rank_selection.is_some()
+                    || checkpointing.is_some())
+            {
+                println!("Early stopping, selecting the number of factors and checkpoints are not supported when blending.");
+                return;
+            }
+            if blend.is_some() && !(probe > 0.0 && probe < 1.0) {
+                println!("The probe fraction must be between 0 and 1.");
+                return;
+            }
+            for (name, member) in blend.iter().flatten() {
+                if member.loss == Loss::Logistic && !member.gradient_descent() {
+                    println!("Cannot blend {}, the logistic loss is not supported.", name);
+                    return;
+                }
+                if member.propensity != Propensity::Uniform && !member.gradient_descent() {
+                    println!(
+                        "Cannot blend {}, inverse propensity weighting is not supported.",
+                        name
+                    );
+                    return;
+                }
+                if member.optimizer != Optimizer::Plain && !member.gradient_based() {
+                    println!(
+                        "Cannot blend {}, the adaptive optimizers are not supported.",
+                        name
+                    );
+                    return;
+                }
+                if !member.model.supports(member.similarity) {
+                    println!("Cannot blend {}, the similarity is not supported.", name);
+                    return;
+                }
+            }
+            let log = training_log(args, &parameters, false);
+            let prediction_model = if let Some(members) = &blend {
+                let Some(model) = ensemble::train(database, members, probe, seed, held_out, &log)
+                else {
+                    println!("The probe set has no votes. Use a larger probe fraction.");
+                    return;
+                };
+                Model::Ensemble(model)
+            } else if let Some(checkpointing) = &checkpointing {
+                Model::Factors(database.train_gradient_descent(
+                    &parameters,
+                    held_out,
+                    &log,
+                    None,
+                    Some(checkpointing),
+                ))
+            } else {
+                database.train_prediction_model(&parameters, held_out, &log)
+            };
+            prediction_model.save(&storage);
+            if checkpointing.is_some() {
+                Checkpoint::remove(&storage);
+            }
+            println!("Saved prediction model to file.");
+            if let Model::Factors(model) = &prediction_model {
+                if let Some(rank_selection) = model.rank_selection() {
+                    print!("{}", rank_selection);
+                }
+            }
+        }
+        Some(("tune", args)) => {
+            let values = |name: &str, defaults: &[usize]| -> Vec<usize> {
+                args.get_many::<usize>(name)
+                    .map_or_else(|| defaults.to_vec(), |values| values.copied().collect())
+            };
+            let rates = |name: &str, defaults: &[f64]| -> Vec<f64> {
+                args.get_many::<f64>(name)
+                    .map_or_else(|| defaults.to_vec(), |values| values.copied().collect())
+            };
+            let space = SearchSpace {
+                latent_factors: values("latent_factors", &[10, 30]),
+                iterations: values("iterations", &[120]),
+                learning_rate: rates("learning_rate", &[0.002, 0.004, 0.008]),
+                regularization: rates("regularization", &[0.01, 0.02, 0.05]),
+            };
+            let search = match args.get_one::<String>("search").map(String::as_str) {
+                Some("random") => Search::Random {
+                    trials: *args.get_one::<usize>("trials").unwrap_or(&20usize),
+                },
+                _ => Search::Grid,
+            };
+            let objective = match args.get_one::<String>("metric").map(String::as_str) {
+                Some("mae") => Objective::Mae,
+                Some("auc") => Objective::Auc,
+                Some("precision") => Objective::Precision,
+                Some("recall") => Objective::Recall,
+                Some("map") => Objective::Map,
+                Some("ndcg") => Objective::Ndcg,
+                Some("debiased-rmse") => Objective::DebiasedRmse,
+                Some("debiased-recall") => Objective::DebiasedRecall,
+                _ => Objective::Rmse,
+            };
+            let folds = *args.get_one::<usize>("folds").unwrap_or(&5usize);
+            if folds < 2 {
+                println!("Cross-validation requires at least 2 folds.");
+                return;
+            }
+            let k = *args.get_one::<usize>("cutoff").unwrap_or(&10usize);
+            let seed = *args.get_one::<u64>("seed").unwrap_or(&0u64);
+            let results_path = args
+                .get_one::<PathBuf>("results")
+                .cloned()
+                .unwrap_or_else(|| storage.data_path("tuning_results.csv"));
+
+            let database = Database::load(&storage);
+            let defaults = TrainingParameters::default();
+            let base = TrainingParameters {
+                model: model_kind(args),
+                algorithm: algorithm(args),
+                biases: args.get_flag("biases"),
+                loss: loss(args),
+                confidence: *args
+                    .get_one::<f64>("confidence")
+                    .unwrap_or(&defaults.confidence),
+                negative_sampling: negative_sampling(args),
+                initialization: initialization(args),
+                propensity: propensity(args),
+                optimizer: optimizer(args),
+                precision: precision(args),
+                similarity: similarity(args),
+                neighbors: *args
+                    .get_one::<usize>("neighbors")
+                    .unwrap_or(&defaults.neighbors),
+                restart: *args.get_one::<f64>("restart").unwrap_or(&defaults.restart),
+                seed,
+                deterministic: args.get_flag("deterministic"),
+                ..defaults
+            };
+            if base.loss == Loss::Logistic && !base.gradient_descent() {
+                println!("The logistic loss is only supported by the gradient descent algorithms.");
+                return;
+            }
+            if base.propensity != Propensity::Uniform && !base.gradient_descent() {
+                println!("Inverse propensity weighting is only supported by the gradient descent algorithms.");
+                return;
+            }
+            if base.optimizer != Optimizer::Plain && !base.gradient_based() {
+                println!("The adaptive optimizers are only supported by the funk, svdpp, sgd and bpr algorithms.");
+                return;
+            }
+            if base.model != ModelKind::Factors
+                && (args.contains_id("latent_factors") || base.biases)
+            {
+                println!("Latent factors and biases are only supported by factor models.");
+                return;
+            }
+            if !base.model.supports(base.similarity) {
+                println!("Item kNN supports the cosine, adjusted-cosine and jaccard similarities, user kNN the cosine, pearson and jaccard similarities.");
+                return;
+            }
+            let candidates = space.candidates(search, &base, seed);
+            if candidates.is_empty() {
+                println!("There are no candidates to evaluate. Try at least one trial.");
+                return;
+            }
+            println!(
+                "Evaluating {} candidates with {}-fold cross-validation...",
+                candidates.len(),
+                folds
+            );
+            let results = tuning::tune(&database, &candidates, folds, objective, k, seed);
+            tuning::write_results(&storage, &results_path, &results);
+            println!("Wrote results to {}.", results_path.display());
+
+            println!("Best candidates:");
+            for result in results.iter().take(5) {
+                let (p, m) = (&result.parameters, &result.metrics);
+                println!(
+                    "  latent factors {}, iterations {}, learning rate {:.5}, regularization {:.5}: RMSE {}, AUC {}, NDCG@{} {}",
+                    p.latent_factors, p.iterations, p.learning_rate, p.regularization, format_metric(m.rmse), format_metric(m.auc), m.k, format_metric(m.ndcg)
+                );
+            }
+
+            println!("Training best candidate on all votes...");
+            let prediction_model = database.train_prediction_model(
+                &results[0].parameters,
+                Split::default(),
+                &TrainingLog::new(true),
             );
+            prediction_model.save(&storage);
+            println!("Saved prediction model to file.");
         }
         Some(("predict", args)) => {
-            let prediction_model = PredictionModel::load();
+            let prediction_model = Model::load(&storage);
             let top = args.get_one::<usize>("top").unwrap_or(&10usize);
             let users: Vec<_> = args.get_many::<String>("USERS").unwrap().collect();
             for user in users {
@@ -76,7 +584,7 @@ fn main() {
             }
         }
         Some(("advertise", args)) => {
-            let prediction_model = PredictionModel::load();
+            let prediction_model = Model::load(&storage);
             let top = args.get_one::<usize>("top").unwrap_or(&10usize);
             let articles: Vec<_> = args.get_many::<String>("ARTICLES").unwrap().collect();
             for article in articles {
@@ -84,6 +592,445 @@ fn main() {
                 println!();
             }
         }
+        Some(("evaluate", args)) => {
+            let prediction_model = Model::load(&storage);
+            let k = *args.get_one::<usize>("cutoff").unwrap_or(&10usize);
+            let held_out = prediction_model.held_out();
+            let (votes, available) = if args.get_flag("validation") {
+                (&held_out.validation, held_out.has_validation())
+            } else {
+                (&held_out.test, held_out.has_test())
+            };
+
+            if !available {
+                println!("The model has no held out votes. Train it with --split to evaluate it.");
+                return;
+            }
+            if let Model::Factors(model) = &prediction_model {
+                if let Some(rank_selection) = model.rank_selection() {
+                    println!("{}", rank_selection);
+                }
+            }
+            let debiasing = propensity(args).debiasing();
+            println!(
+                "{}",
+                Metrics::evaluate(&prediction_model, votes, k, debiasing)
+            );
+        }
+        Some(("benchmark", args)) => {
+            let names = args.get_many::<String>("algorithm").map_or_else(
+                || vec!["funk", "sgd"],
+                |names| names.map(String::as_str).collect(),
+            );
+            let iterations = args
+                .get_many::<usize>("iterations")
+                .map(|values| values.copied().collect::<Vec<_>>());
+            let learning_rates = args
+                .get_many::<f64>("learning_rate")
+                .map(|values| values.copied().collect::<Vec<_>>());
+            let precisions = args
+                .get_many::<String>("precision")
+                .map_or_else(|| vec!["f32"], |names| names.map(String::as_str).collect());
+            let test = *args.get_one::<f64>("test_fraction").unwrap_or(&0.1f64);
+            let k = *args.get_one::<usize>("cutoff").unwrap_or(&10usize);
+            let seed = *args.get_one::<u64>("seed").unwrap_or(&0u64);
+
+            // all algorithms train on the same votes and are tested on the same held out votes
+            let database = Database::load(&storage);
+            let (database, held_out) = Split::new(
+                &database,
+                SplitStrategy::Random {
+                    validation: 0.0,
+                    test,
+                },
+                seed,
+            );
+
+            let defaults = TrainingParameters::default();
+            for (index, name) in names.iter().enumerate() {
+                let parameters = TrainingParameters {
+                    model: parse_model_kind(name),
+                    algorithm: parse_algorithm(name),
+                    latent_factors: *args
+                        .get_one::<usize>("latent_factors")
+                        .unwrap_or(&defaults.latent_factors),
+                    iterations: per_algorithm(&iterations, index, defaults.iterations),
+                    learning_rate: per_algorithm(&learning_rates, index, defaults.learning_rate),
+                    regularization: *args
+                        .get_one::<f64>("regularization")
+                        .unwrap_or(&defaults.regularization),
+                    biases: args.get_flag("biases"),
+                    loss: loss(args),
+                    propensity: propensity(args),
+                    optimizer: optimizer(args),
+                    similarity: similarity(args),
+                    neighbors: *args
+                        .get_one::<usize>("neighbors")
+                        .unwrap_or(&defaults.neighbors),
+                    restart: *args.get_one::<f64>("restart").unwrap_or(&defaults.restart),
+                    seed,
+                    ..defaults
+                };
+                if parameters.loss == Loss::Logistic && !parameters.gradient_descent() {
+                    println!("{}: skipped, the logistic loss is not supported.", name);
+                    continue;
+                }
+                if parameters.propensity != Propensity::Uniform && !parameters.gradient_descent() {
+                    println!(
+                        "{}: skipped, inverse propensity weighting is not supported.",
+                        name
+                    );
+                    continue;
+                }
+                if parameters.optimizer != Optimizer::Plain && !parameters.gradient_based() {
+                    println!(
+                        "{}: skipped, the adaptive optimizers are not supported.",
+                        name
+                    );
+                    continue;
+                }
+                if !parameters.model.supports(parameters.similarity) {
+                    println!("{}: skipped, the similarity is not supported.", name);
+                    continue;
+                }
+
+                // only funk and svdpp compute in the chosen precision, the others are trained once
+                let runs = if parameters.incremental() {
+                    &precisions[..]
+                } else {
+                    &precisions[..1]
+                };
+                for &precision in runs {
+                    let parameters = TrainingParameters {
+                        precision: parse_precision(precision),
+                        ..parameters
+                    };
+                    let label = if runs.len() > 1 {
+                        format!("{} ({})", name, precision)
+                    } else {
+                        name.to_string()
+                    };
+
+                    let now = std::time::Instant::now();
+                    let prediction_model = database.clone().train_prediction_model(
+                        &parameters,
+                        held_out.clone(),
+                        &TrainingLog::default(),
+                    );
+                    let elapsed = now.elapsed();
+                    let metrics = Metrics::evaluate(
+                        &prediction_model,
+                        &held_out.test,
+                        k,
+                        parameters.propensity.debiasing(),
+                    );
+                    println!(
+                        "{}: trained in {}ms ({} iterations, learning rate {}): RMSE {}, AUC {}, NDCG@{} {}",
+                        label, elapsed.as_millis(), parameters.iterations, parameters.learning_rate, format_metric(metrics.rmse), format_metric(metrics.auc), k, format_metric(metrics.ndcg)
+                    );
+                }
+            }
+        }
+        Some(("report", args)) => {
+            let runs: Vec<_> = args
+                .get_many::<PathBuf>("LOGS")
+                .unwrap()
+                .map(|path| Run::load(path))
+                .collect();
+            let curve = match args.get_one::<String>("curve").map(String::as_str) {
+                Some("validation") => Curve::Validation,
+                _ => Curve::Training,
+            };
+
+            for run in &runs {
+                println!("{}", run.summary());
+            }
+            println!();
+            let Some(plot) = report::plot_terminal(&runs, curve) else {
+                println!("The logs contain no values for this loss. Train with a validation split to log the validation loss.");
+                return;
+            };
+            print!("{}", plot);
+            if let Some(path) = args.get_one::<PathBuf>("svg") {
+                match report::write_svg(path, &runs, curve) {
+                    Ok(true) => println!("Wrote loss curves to {}.", path.display()),
+                    Ok(false) => {
+                        println!("There are no loss curves to write to {}.", path.display())
+                    }
+                    Err(error) => println!(
+                        "Failed to write loss curves to {}: {}",
+                        path.display(),
+                        error
+                    ),
+                }
+            }
+        }
+        Some(("restore", args)) => {
+            let path = match args.get_one::<String>("FILE").map(String::as_str) {
+                Some("model") => &storage.model_path,
+                _ => &storage.database_path,
+            };
+
+            if args.get_flag("list") {
+                let generations = storage.generations(path);
+                if generations.is_empty() {
+                    println!("No backups of {} found.", path.display());
+                }
+                for (generation, modified) in generations {
+                    let age = modified.elapsed().unwrap_or_default();
+                    println!(
+                        "Generation {}: written {} minutes ago",
+                        generation,
+                        age.as_secs() / 60
+                    );
+                }
+                return;
+            }
+
+            let generation = *args.get_one::<usize>("generation").unwrap_or(&1usize);
+            if let Err(error) = storage.restore(path, generation) {
+                println!(
+                    "Failed to restore backup generation {} of {}: {}",
+                    generation,
+                    path.display(),
+                    error
+                );
+                match storage.generations(path).len() {
+                    0 => println!("There are no backups of {}.", path.display()),
+                    1 => println!("Only generation 1 exists."),
+                    count => println!("Generations 1 to {} exist.", count),
+                }
+                return;
+            }
+            println!(
+                "Restored {} from backup generation {}.",
+                path.display(),
+                generation
+            );
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Creates the log of the `train` command, which reports to the console and, with `--log`, to a
+/// file. A resumed training continues its log instead of replacing it.
+fn training_log(args: &ArgMatches, parameters: &TrainingParameters, resume: bool) -> TrainingLog {
+    match args.get_one::<PathBuf>("log") {
+        Some(path) => TrainingLog::with_file(true, path, parameters, resume)
+            .expect("Failed to create training log."),
+        None => TrainingLog::new(true),
+    }
+}
+
+/// Reads the split strategy from the arguments of the `train` command. Returns `None` if no votes
+/// should be held out. With early stopping, validation votes are held out by default, using a
+/// random split if no strategy is given.
+fn split_strategy(args: &ArgMatches) -> Option<SplitStrategy> {
+    let early_stopping = args.contains_id("early_stopping") || args.contains_id("select_rank");
+    let test = *args.get_one::<f64>("test_fraction").unwrap_or(&0.1f64);
+    let validation = *args
+        .get_one::<f64>("validation_fraction")
+        .unwrap_or(if early_stopping { &0.1f64 } else { &0.0f64 });
+    let split = match args.get_one::<String>("split") {
+        Some(split) => split.as_str(),
+        None if early_stopping => {
+            return Some(SplitStrategy::Random {
+                validation,
+                test: 0.0,
+            })
+        }
+        None => return None,
+    };
+
+    match split {
+        "random" => Some(SplitStrategy::Random { validation, test }),
+        "leave-k-out" => Some(SplitStrategy::LeaveKOut {
+            validation: *args
+                .get_one::<usize>("leave_out_validation")
+                .unwrap_or(if early_stopping { &1usize } else { &0usize }),
+            test: *args.get_one::<usize>("leave_out").unwrap_or(&1usize),
+        }),
+        "article-order" => Some(SplitStrategy::ArticleOrder { validation, test }),
         _ => unreachable!(),
     }
 }
+
+/// Reads the kind of prediction model from the arguments of the `train` or `tune` command.
+fn model_kind(args: &ArgMatches) -> ModelKind {
+    args.get_one::<String>("model")
+        .map_or(ModelKind::Factors, |name| parse_model_kind(name))
+}
+
+/// Converts the name of a kind of prediction model on the command line into the kind. Names of
+/// factor model algorithms are factor models.
+fn parse_model_kind(name: &str) -> ModelKind {
+    match name {
+        "item-knn" => ModelKind::ItemKnn,
+        "user-knn" => ModelKind::UserKnn,
+        "popularity" => ModelKind::Popularity,
+        "pagerank" => ModelKind::PageRank,
+        _ => ModelKind::Factors,
+    }
+}
+
+/// Reads the training algorithm from the arguments of the `train` or `tune` command.
+fn algorithm(args: &ArgMatches) -> Algorithm {
+    args.get_one::<String>("algorithm")
+        .map_or(Algorithm::Funk, |name| parse_algorithm(name))
+}
+
+/// Converts the name of a training algorithm on the command line into the algorithm.
+fn parse_algorithm(name: &str) -> Algorithm {
+    match name {
+        "svdpp" => Algorithm::SvdPlusPlus,
+        "sgd" => Algorithm::Sgd,
+        "als" => Algorithm::Als,
+        "implicit-als" => Algorithm::ImplicitAls,
+        "bpr" => Algorithm::Bpr,
+        _ => Algorithm::Funk,
+    }
+}
+
+/// Reads the negative sampling of BPR from the arguments of the `train` or `tune` command.
+fn negative_sampling(args: &ArgMatches) -> NegativeSampling {
+    match args.get_one::<String>("negatives").map(String::as_str) {
+        Some("unvoted") => NegativeSampling::Unvoted,
+        Some("downvoted") => NegativeSampling::Downvoted,
+        _ => NegativeSampling::Any,
+    }
+}
+
+/// Reads the loss from the arguments of the `train` or `tune` command.
+fn loss(args: &ArgMatches) -> Loss {
+    match args.get_one::<String>("loss").map(String::as_str) {
+        Some("logistic") => Loss::Logistic,
+        _ => Loss::Squared,
+    }
+}
+
+/// Reads the propensity estimate for inverse propensity weighting from the arguments of the
+/// `train`, `tune` or `benchmark` command, or for the debiased metrics from the arguments of the
+/// `evaluate` command.
+fn propensity(args: &ArgMatches) -> Propensity {
+    match args.get_one::<String>("propensity").map(String::as_str) {
+        Some("popularity") => Propensity::Popularity,
+        Some("age") => Propensity::Age,
+        Some("series") => Propensity::Series,
+        _ => Propensity::Uniform,
+    }
+}
+
+/// Reads the optimizer of the gradient-based algorithms from the arguments of the `train`, `tune`
+/// or `benchmark` command.
+fn optimizer(args: &ArgMatches) -> Optimizer {
+    match args.get_one::<String>("optimizer").map(String::as_str) {
+        Some("adagrad") => Optimizer::AdaGrad,
+        Some("rmsprop") => Optimizer::RmsProp,
+        Some("adam") => Optimizer::Adam,
+        _ => Optimizer::Plain,
+    }
+}
+
+/// Reads the precision of full-batch gradient descent from the arguments of the `train` or `tune`
+/// command.
+fn precision(args: &ArgMatches) -> Precision {
+    args.get_one::<String>("precision")
+        .map_or_else(Precision::default, |name| parse_precision(name))
+}
+
+/// Parses the name of a floating point precision, as given to `--precision`.
+fn parse_precision(name: &str) -> Precision {
+    match name {
+        "f64" => Precision::Double,
+        _ => Precision::Single,
+    }
+}
+
+/// Reads the similarity of the kNN algorithms from the arguments of the `train`, `tune` or
+/// `benchmark` command.
+fn similarity(args: &ArgMatches) -> Similarity {
+    match args.get_one::<String>("similarity").map(String::as_str) {
+        Some("adjusted-cosine") => Similarity::AdjustedCosine,
+        Some("pearson") => Similarity::Pearson,
+        Some("jaccard") => Similarity::Jaccard,
+        _ => Similarity::Cosine,
+    }
+}
+
+/// Picks the value for the algorithm at `index` from values given per algorithm on the command
+/// line. If fewer values than algorithms are given, the last value is reused.
+fn per_algorithm<T: Copy>(values: &Option<Vec<T>>, index: usize, default: T) -> T {
+    values
+        .as_ref()
+        .map_or(default, |values| values[index.min(values.len() - 1)])
+}
+
+/// Reads how the regularization grows with the number of votes of a user or article from the
+/// arguments of the `train` command.
+fn regularization_scaling(args: &ArgMatches) -> RegularizationScaling {
+    match args
+        .get_one::<String>("regularization_scaling")
+        .map(String::as_str)
+    {
+        Some("constant") => RegularizationScaling::Constant,
+        Some("frequency") => RegularizationScaling::Frequency {
+            exponent: *args.get_one::<f64>("frequency_exponent").unwrap_or(&0.5f64),
+        },
+        _ => RegularizationScaling::WeightedLambda,
+    }
+}
+
+/// Reads the initialization of the factors from the arguments of the `train` or `tune` command.
+/// Without `--init`, the algorithm chooses its own initialization.
+fn initialization(args: &ArgMatches) -> Option<Initialization> {
+    let scale = *args.get_one::<f64>("init_scale").unwrap_or(&0.1f64);
+    match args.get_one::<String>("init").map(String::as_str) {
+        Some("uniform") => Some(Initialization::Uniform { scale }),
+        Some("normal") => Some(Initialization::Normal { scale }),
+        Some("constant") => Some(Initialization::Constant { value: scale }),
+        _ => None,
+    }
+}
+
+/// Parses a fraction of votes or articles on the command line, which must be between 0 and 1.
+fn fraction(value: &str) -> Result<f64, String> {
+    let fraction = value.parse::<f64>().map_err(|error| error.to_string())?;
+    if fraction > 0.0 && fraction < 1.0 {
+        Ok(fraction)
+    } else {
+        Err("must be between 0 and 1".to_string())
+    }
+}
+
+/// Parses a learning rate or regularization to try on the command line, which must be positive,
+/// since random search samples them on a logarithmic scale.
+fn positive(value: &str) -> Result<f64, String> {
+    let positive = value.parse::<f64>().map_err(|error| error.to_string())?;
+    if positive.is_finite() && positive > 0.0 {
+        Ok(positive)
+    } else {
+        Err("must be a finite number greater than 0".to_string())
+    }
+}
+
+/// Parses the restart probability of the PageRank walk on the command line, which must be greater
+/// than 0 so the walk converges, and at most 1.
+fn restart(value: &str) -> Result<f64, String> {
+    let restart = value.parse::<f64>().map_err(|error| error.to_string())?;
+    if restart > 0.0 && restart <= 1.0 {
+        Ok(restart)
+    } else {
+        Err("must be greater than 0 and at most 1".to_string())
+    }
+}
+
+/// Parses the scale of the initial factors on the command line, which must be finite and not
+/// negative.
+fn scale(value: &str) -> Result<f64, String> {
+    let scale = value.parse::<f64>().map_err(|error| error.to_string())?;
+    if scale.is_finite() && scale >= 0.0 {
+        Ok(scale)
+    } else {
+        Err("must be a finite number that is not negative".to_string())
+    }
+}
@@ -0,0 +1,294 @@
+use crate::hogwild::SharedMatrix;
+use nalgebra::{DMatrix, DVector};
+use serde::{Deserialize, Serialize};
+
+/// Added to the root of the squared gradients, so parameters without gradient so far do not
+/// divide by zero.
+const EPSILON: f64 = 1e-8;
+
+/// How much of the average of the squared gradients RMSProp keeps in every update.
+const RMSPROP_DECAY: f64 = 0.9;
+
+/// How much of the averages of the gradients and the squared gradients Adam keeps in every
+/// update, the defaults of Kingma and Ba.
+const ADAM_FIRST_DECAY: f64 = 0.9;
+const ADAM_SECOND_DECAY: f64 = 0.999;
+
+/// How the gradient of a parameter is turned into its update. The adaptive optimizers scale the
+/// learning rate of every parameter by its own gradient history, so users and articles with many
+/// votes and thus large gradients take smaller steps than those with a few votes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum Optimizer {
+    /// The gradient times the learning rate, the same for all parameters.
+    #[default]
+    Plain,
+
+    /// The step is divided by the root of the sum of all squared gradients of the parameter so
+    /// far, so it shrinks over the training.
+    AdaGrad,
+
+    /// Like AdaGrad, but with a decaying average of the squared gradients, so the steps only
+    /// shrink while the gradients are large.
+    RmsProp,
+
+    /// RMSProp applied to a decaying average of the gradients instead of the gradient itself,
+    /// with both averages corrected for starting at zero.
+    Adam,
+}
+
+impl Optimizer {
+    /// Turns the `gradient` of a parameter into its update. `first` and `second` are the running
+    /// averages of the parameter's gradients and squared gradients, `powers` the decays of the
+    /// averages raised to the number of previous updates, which Adam needs to correct them.
+    pub(crate) fn update(
+        self,
+        learning_rate: f64,
+        gradient: f64,
+        first: &mut f64,
+        second: &mut f64,
+        powers: [f64; 2],
+    ) -> f64 {
+        match self {
+            Optimizer::Plain => learning_rate * gradient,
+            Optimizer::AdaGrad => {
+                *second += gradient * gradient;
+                learning_rate * gradient / (second.sqrt() + EPSILON)
+            }
+            Optimizer::RmsProp => {
+                *second = RMSPROP_DECAY * *second + (1.0 - RMSPROP_DECAY) * gradient * gradient;
+                learning_rate * gradient / (second.sqrt() + EPSILON)
+            }
+            Optimizer::Adam => {
+                *first = ADAM_FIRST_DECAY * *first + (1.0 - ADAM_FIRST_DECAY) * gradient;
+                *second =
+                    ADAM_SECOND_DECAY * *second + (1.0 - ADAM_SECOND_DECAY) * gradient * gradient;
+                let first_corrected = *first / (1.0 - powers[0] * ADAM_FIRST_DECAY);
+                let second_corrected = *second / (1.0 - powers[1] * ADAM_SECOND_DECAY);
+                learning_rate * first_corrected / (second_corrected.sqrt() + EPSILON)
+            }
+        }
+    }
+}
+
+/// The powers of the decays of Adam after one more update.
+fn decay(powers: [f64; 2]) -> [f64; 2] {
+    [powers[0] * ADAM_FIRST_DECAY, powers[1] * ADAM_SECOND_DECAY]
+}
+
+/// The running averages of the optimizer for a vector of parameters that are all updated at once,
+/// as in full-batch gradient descent. They are saved in checkpoints, so a resumed training
+/// continues with the same step sizes.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct Moments {
+    first: Vec<f64>,
+    second: Vec<f64>,
+
+    /// The decays of Adam raised to the number of updates so far.
+    powers: [f64; 2],
+}
+
+impl Moments {
+    /// Creates the averages for `len` parameters. The plain optimizer needs none.
+    pub(crate) fn new(optimizer: Optimizer, len: usize) -> Self {
+        let len = if optimizer == Optimizer::Plain {
+            0
+        } else {
+            len
+        };
+        Self {
+            first: vec![0.0; len],
+            second: vec![0.0; len],
+            powers: [1.0; 2],
+        }
+    }
+
+    /// Whether the averages cover `len` parameters updated by `optimizer`.
+    pub(crate) fn fits(&self, optimizer: Optimizer, len: usize) -> bool {
+        let len = if optimizer == Optimizer::Plain {
+            0
+        } else {
+            len
+        };
+        self.first.len() == len && self.second.len() == len
+    }
+
+    /// Forgets all gradients, when the parameters start to be trained anew.
+    pub(crate) fn reset(&mut self) {
+        self.first.fill(0.0);
+        self.second.fill(0.0);
+        self.powers = [1.0; 2];
+    }
+
+    /// Turns the gradient of every parameter into its update, in place.
+    pub(crate) fn update(
+        &mut self,
+        optimizer: Optimizer,
+        learning_rate: f64,
+        gradient: &mut DVector<f64>,
+    ) {
+        if optimizer == Optimizer::Plain {
+            *gradient *= learning_rate;
+            return;
+        }
+        for (index, gradient) in gradient.iter_mut().enumerate() {
+            *gradient = optimizer.update(
+                learning_rate,
+                *gradient,
+                &mut self.first[index],
+                &mut self.second[index],
+                self.powers,
+            );
+        }
+        self.powers = decay(self.powers);
+    }
+}
+
+impl Default for Moments {
+    /// No averages, as needed by the plain optimizer, with the powers of a first update.
+    fn default() -> Self {
+        Self::new(Optimizer::Plain, 0)
+    }
+}
+
+/// The running averages of the optimizer for a matrix of parameters that is updated from multiple
+/// threads without locking, as in the Hogwild trainers. All parameters of a row are updated
+/// together, so the number of updates is tracked per row.
+pub(crate) struct SharedMoments {
+    optimizer: Optimizer,
+    first: SharedMatrix,
+    second: SharedMatrix,
+
+    /// The decays of Adam raised to the number of updates of each row.
+    powers: SharedMatrix,
+}
+
+impl SharedMoments {
+    /// Creates the averages for a matrix with the given shape. The plain optimizer needs none.
+    pub(crate) fn new(optimizer: Optimizer, rows: usize, columns: usize) -> Self {
+        let (rows, columns) = if optimizer == Optimizer::Plain {
+            (0, 0)
+        } else {
+            (rows, columns)
+        };
+        Self {
+            optimizer,
+            first: SharedMatrix::new(&DMatrix::zeros(rows, columns)),
+            second: SharedMatrix::new(&DMatrix::zeros(rows, columns)),
+            powers: SharedMatrix::new(&DMatrix::from_element(rows, 2, 1.0)),
+        }
+    }
+
+    /// Turns the gradient of the parameter at the given position into its update.
+    pub(crate) fn update(
+        &self,
+        learning_rate: f64,
+        row: usize,
+        column: usize,
+        gradient: f64,
+    ) -> f64 {
+        if self.optimizer == Optimizer::Plain {
+            return learning_rate * gradient;
+        }
+        let (mut first, mut second) = (self.first.get(row, column), self.second.get(row, column));
+        let update = self.optimizer.update(
+            learning_rate,
+            gradient,
+            &mut first,
+            &mut second,
+            [self.powers.get(row, 0), self.powers.get(row, 1)],
+        );
+        self.first.set(row, column, first);
+        self.second.set(row, column, second);
+        update
+    }
+
+    /// Counts an update of all parameters of a row, once all of them have been updated.
+    pub(crate) fn finish_row(&self, row: usize) {
+        if self.optimizer == Optimizer::Adam {
+            let powers = decay([self.powers.get(row, 0), self.powers.get(row, 1)]);
+            self.powers.set(row, 0, powers[0]);
+            self.powers.set(row, 1, powers[1]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The update of a parameter over several steps with the given gradients.
+    fn updates(optimizer: Optimizer, gradients: &[f64]) -> Vec<f64> {
+        let (mut first, mut second, mut powers) = (0.0, 0.0, [1.0; 2]);
+        gradients
+            .iter()
+            .map(|&gradient| {
+                let update = optimizer.update(0.1, gradient, &mut first, &mut second, powers);
+                powers = decay(powers);
+                update
+            })
+            .collect()
+    }
+
+    /// Asserts that the updates match up to the effect of the epsilon in the divisors.
+    fn assert_close(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len());
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!((actual - expected).abs() < 1e-7, "{:?}", actual);
+        }
+    }
+
+    #[test]
+    fn plain_scales_by_learning_rate() {
+        assert_close(&updates(Optimizer::Plain, &[2.0, -4.0]), &[0.2, -0.4]);
+    }
+
+    #[test]
+    fn adagrad_divides_by_root_of_summed_squares() {
+        // the squared gradients sum to 4 and then 4 + 16
+        let expected = [0.1 * 2.0 / 2.0, 0.1 * -4.0 / 20f64.sqrt()];
+        assert_close(&updates(Optimizer::AdaGrad, &[2.0, -4.0]), &expected);
+    }
+
+    #[test]
+    fn rmsprop_divides_by_root_of_decaying_average() {
+        // the averages of the squared gradients are 0.1 * 4 and then 0.9 * 0.4 + 0.1 * 16
+        let expected = [0.1 * 2.0 / 0.4f64.sqrt(), 0.1 * -4.0 / 1.96f64.sqrt()];
+        assert_close(&updates(Optimizer::RmsProp, &[2.0, -4.0]), &expected);
+    }
+
+    #[test]
+    fn adam_corrects_the_averages_for_starting_at_zero() {
+        // the corrected averages of the first step are the gradient and its square
+        assert_close(&updates(Optimizer::Adam, &[2.0]), &[0.1]);
+
+        // the corrected averages of the second step
+        let first = (0.9 * 0.1 * 2.0 + 0.1 * -4.0) / (1.0 - 0.81);
+        let second: f64 = (0.999 * 0.001 * 4.0 + 0.001 * 16.0) / (1.0 - 0.999 * 0.999);
+        let expected = [0.1, 0.1 * first / second.sqrt()];
+        assert_close(&updates(Optimizer::Adam, &[2.0, -4.0]), &expected);
+    }
+
+    #[test]
+    fn moments_and_shared_moments_agree() {
+        let gradients = [[2.0, -1.0], [-4.0, 0.5], [1.0, 3.0]];
+        for optimizer in [
+            Optimizer::Plain,
+            Optimizer::AdaGrad,
+            Optimizer::RmsProp,
+            Optimizer::Adam,
+        ] {
+            let mut moments = Moments::new(optimizer, 2);
+            let shared = SharedMoments::new(optimizer, 1, 2);
+            for step in gradients {
+                let mut gradient = DVector::from_row_slice(&step);
+                moments.update(optimizer, 0.1, &mut gradient);
+                let shared_updates = [
+                    shared.update(0.1, 0, 0, step[0]),
+                    shared.update(0.1, 0, 1, step[1]),
+                ];
+                shared.finish_row(0);
+                assert_close(gradient.as_slice(), &shared_updates);
+            }
+        }
+    }
+}
@@ -0,0 +1,411 @@
+use crate::database::{Database, TrainingParameters};
+use crate::evaluation::Split;
+use crate::recommender::{ModelRef, Recommender};
+use crate::report::TrainingLog;
+use nalgebra::DVector;
+use nalgebra_sparse::ops::serial::spmm_csr_dense;
+use nalgebra_sparse::ops::Op;
+use nalgebra_sparse::{CooMatrix, CsrMatrix};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+/// The total change of all visit probabilities below which the walk has converged.
+const TOLERANCE: f64 = 1e-8;
+
+/// Personalized PageRank on the bipartite graph of users and articles, in which every upvote
+/// connects its user and article. A random walker follows the upvotes from articles to their
+/// upvoters and from users to the articles they upvoted, and at every step jumps back to where
+/// it started with the restart probability. The articles the walker visits most often from the
+/// upvoted articles of a user are recommended to the user, and the users visited most often from
+/// the upvoters of an article are the ones it is advertised to. Nothing is learned in advance;
+/// every query walks the whole graph by power iteration on the sparse transition matrices. Single
+/// predictions reuse the walk of the last user, so predicting the votes of a user one by one only
+/// walks once.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct PageRankModel {
+    /// The database the model was trained on, shared with the other members of an ensemble.
+    pub(crate) database: Arc<Database>,
+
+    /// The probability that the walker jumps back to the start at every step.
+    restart: f64,
+
+    #[serde(default)]
+    held_out: Split,
+
+    /// The ids of the articles each user voted on, sorted. They are derived from the database and
+    /// therefore not stored.
+    #[serde(skip)]
+    user_votes: Vec<Vec<usize>>,
+
+    /// The transition probabilities of the walk. They are derived from the database and
+    /// therefore not stored.
+    #[serde(skip, default = "Graph::empty")]
+    graph: Graph,
+
+    /// The walk of the last user whose vote was predicted with [`Recommender::predict`].
+    #[serde(skip)]
+    last_walk: LastWalk,
+}
+
+/// The visit probabilities of all articles in the walk from the user whose vote was predicted
+/// last. Copies of the model start without it.
+#[derive(Debug, Default)]
+struct LastWalk(Mutex<Option<(usize, Arc<DVector<f64>>)>>);
+
+impl Clone for LastWalk {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl LastWalk {
+    /// Returns the walk from the user, which is computed by `walk` unless it was the last one.
+    /// The lock is not held while walking, so parallel predictions for different users do not
+    /// wait for each other.
+    fn get(&self, user_id: usize, walk: impl FnOnce() -> DVector<f64>) -> Arc<DVector<f64>> {
+        if let Some((last_user_id, articles)) = &*self.0.lock().unwrap() {
+            if *last_user_id == user_id {
+                return articles.clone();
+            }
+        }
+        let articles = Arc::new(walk());
+        *self.0.lock().unwrap() = Some((user_id, articles.clone()));
+        articles
+    }
+}
+
+/// The transition matrices of the walk. Column `j` of each matrix holds the probabilities of
+/// stepping from user or article `j` to each of its neighbors, which are all equally likely.
+#[derive(Clone, Debug)]
+struct Graph {
+    /// From users to the articles they upvoted, with a row per article and a column per user.
+    to_articles: CsrMatrix<f64>,
+
+    /// From articles to their upvoters, with a row per user and a column per article.
+    to_users: CsrMatrix<f64>,
+}
+
+impl Graph {
+    /// A graph without users and articles, as placeholder until the model is aggregated.
+    fn empty() -> Self {
+        Self {
+            to_articles: CsrMatrix::zeros(0, 0),
+            to_users: CsrMatrix::zeros(0, 0),
+        }
+    }
+
+    /// Creates the graph of the upvotes in the database.
+    fn new(database: &Database) -> Self {
+        let (users, articles) = (database.user_count(), database.article_count());
+        let upvotes = database
+            .article_votes()
+            .iter()
+            .enumerate()
+            .flat_map(|(article_id, votes)| {
+                votes
+                    .iter()
+                    .filter(|&&(_, vote)| vote)
+                    .map(move |&(user_id, _)| (user_id, article_id))
+            })
+            .collect::<Vec<_>>();
+        let (mut user_degrees, mut article_degrees) = (vec![0.0; users], vec![0.0; articles]);
+        for &(user_id, article_id) in &upvotes {
+            user_degrees[user_id] += 1.0;
+            article_degrees[article_id] += 1.0;
+        }
+
+        let mut to_articles = CooMatrix::new(articles, users);
+        let mut to_users = CooMatrix::new(users, articles);
+        for &(user_id, article_id) in &upvotes {
+            to_articles.push(article_id, user_id, 1.0 / user_degrees[user_id]);
+            to_users.push(user_id, article_id, 1.0 / article_degrees[article_id]);
+        }
+        Self {
+            to_articles: CsrMatrix::from(&to_articles),
+            to_users: CsrMatrix::from(&to_users),
+        }
+    }
+}
+
+impl PageRankModel {
+    /// Recomputes the voted articles of all users and the graph from the database the model was
+    /// trained on.
+    pub(crate) fn aggregate(&mut self) {
+        self.user_votes = self.database.user_votes();
+        self.graph = Graph::new(&self.database);
+        self.last_walk = LastWalk::default();
+    }
+
+    /// Walks the graph from the users and articles marked in the start vectors, each of them
+    /// equally likely, and returns how likely the walker is at each user and each article in the
+    /// long run. The walker also jumps back to the start from users and articles without upvotes,
+    /// so no probability gets lost. Without any start, the walk starts anywhere.
+    fn walk(
+        &self,
+        mut start_users: DVector<f64>,
+        mut start_articles: DVector<f64>,
+    ) -> [DVector<f64>; 2] {
+        let starts = start_users.sum() + start_articles.sum();
+        if starts == 0.0 {
+            start_users.fill(1.0);
+            start_articles.fill(1.0);
+        }
+        let total = start_users.sum() + start_articles.sum();
+        start_users /= total;
+        start_articles /= total;
+
+        let mut users = start_users.clone();
+        let mut articles = start_articles.clone();
+        let (mut next_users, mut next_articles) = (users.clone(), articles.clone());
+        let max_steps = self.max_steps();
+        for step in 1..=max_steps {
+            next_users.copy_from(&start_users);
+            next_articles.copy_from(&start_articles);
+            let follow = 1.0 - self.restart;
+            spmm_csr_dense(
+                self.restart,
+                &mut next_users,
+                follow,
+                Op::NoOp(&self.graph.to_users),
+                Op::NoOp(&articles),
+            );
+            spmm_csr_dense(
+                self.restart,
+                &mut next_articles,
+                follow,
+                Op::NoOp(&self.graph.to_articles),
+                Op::NoOp(&users),
+            );
+            let lost = 1.0 - next_users.sum() - next_articles.sum();
+            next_users.axpy(lost, &start_users, 1.0);
+            next_articles.axpy(lost, &start_articles, 1.0);
+
+            let change =
+                (&next_users - &users).lp_norm(1) + (&next_articles - &articles).lp_norm(1);
+            std::mem::swap(&mut users, &mut next_users);
+            std::mem::swap(&mut articles, &mut next_articles);
+            if change < TOLERANCE {
+                break;
+            }
+            if step == max_steps {
+                println!(
+                    "The walk did not converge in {} steps, the visit probabilities still changed by {:e}.",
+                    max_steps, change
+                );
+            }
+        }
+        [users, articles]
+    }
+
+    /// The most steps of power iteration per walk. The total change of all visit probabilities
+    /// is at most 2 in the first step and shrinks at least by the factor `1 - restart` in every
+    /// further step, so this many steps bring it below [`TOLERANCE`]. A small restart probability
+    /// takes many steps, e.g. about 1900 for 0.01, and a restart probability of 1 a single one.
+    fn max_steps(&self) -> usize {
+        ((TOLERANCE / 2.0).ln() / (1.0 - self.restart).ln()).ceil() as usize + 1
+    }
+
+    /// The visit probabilities of all articles in a walk that starts at the articles the user
+    /// upvoted. A user without upvotes starts anywhere, which ranks the articles by their global
+    /// PageRank.
+    fn walk_from_user(&self, user_id: usize) -> DVector<f64> {
+        let mut start = DVector::zeros(self.database.article_count());
+        for &article_id in self.graph.to_users.row(user_id).col_indices() {
+            start[article_id] = 1.0;
+        }
+        let [_, articles] = self.walk(DVector::zeros(self.database.user_count()), start);
+        articles
+    }
+
+    /// The visit probabilities of all users in a walk that starts at the upvoters of the article.
+    /// An article without upvotes starts anywhere.
+    fn walk_from_article(&self, article_id: usize) -> DVector<f64> {
+        let mut start = DVector::zeros(self.database.user_count());
+        for &user_id in self.graph.to_articles.row(article_id).col_indices() {
+            start[user_id] = 1.0;
+        }
+        let [users, _] = self.walk(start, DVector::zeros(self.database.article_count()));
+        users
+    }
+}
+
+impl Recommender for PageRankModel {
+    fn database(&self) -> &Database {
+        &self.database
+    }
+
+    fn held_out(&self) -> &Split {
+        &self.held_out
+    }
+
+    fn voted_articles(&self, user_id: usize) -> &[usize] {
+        &self.user_votes[user_id]
+    }
+
+    fn predict(&self, user_id: usize, article_id: usize) -> f64 {
+        self.last_walk.get(user_id, || self.walk_from_user(user_id))[article_id]
+    }
+
+    fn predict_articles(&self, user_id: usize, article_ids: &[usize]) -> Vec<f64> {
+        let articles = self.walk_from_user(user_id);
+        article_ids
+            .iter()
+            .map(|&article_id| articles[article_id])
+            .collect()
+    }
+
+    fn predict_users(&self, article_id: usize, user_ids: &[usize]) -> Vec<f64> {
+        let users = self.walk_from_article(article_id);
+        user_ids.iter().map(|&user_id| users[user_id]).collect()
+    }
+
+    /// The predictions are the probabilities of the walker being at a user or article, not
+    /// votes.
+    fn describe_prediction(&self, prediction: f64) -> String {
+        format!("visit probability: {:.3}%", prediction * 100.0)
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        serde_cbor::to_vec(&ModelRef::PageRank(self)).unwrap()
+    }
+}
+
+/// Creates a personalized PageRank model. There is nothing to learn, so this only builds the
+/// graph of the upvotes, which every query walks anew.
+pub(crate) fn train(
+    database: Database,
+    parameters: &TrainingParameters,
+    held_out: Split,
+    log: &TrainingLog,
+) -> PageRankModel {
+    let mut model = PageRankModel {
+        database: Arc::new(database),
+        restart: parameters.restart,
+        held_out,
+        user_votes: Vec::new(),
+        graph: Graph::empty(),
+        last_walk: LastWalk::default(),
+    };
+    model.aggregate();
+    if log.verbose {
+        println!(
+            "Built the graph of {} upvotes between {} users and {} articles.",
+            model.graph.to_users.nnz(),
+            model.database.user_count(),
+            model.database.article_count()
+        );
+    }
+    model
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::DMatrix;
+
+    /// A model of three users and four articles: user 0 upvoted articles 0 and 1, user 1 articles
+    /// 1 and 2, and user 2 downvoted article 3, which has no upvotes.
+    fn model(restart: f64) -> PageRankModel {
+        let mut database = Database::new();
+        for user in ["a", "b", "c"] {
+            database.add_user(user.to_string());
+        }
+        let votes = [
+            vec![(0, true)],
+            vec![(0, true), (1, true)],
+            vec![(1, true)],
+            vec![(2, false)],
+        ];
+        for (article, votes) in votes.into_iter().enumerate() {
+            database.add_article(format!("scp-{}", article), String::new(), votes);
+        }
+        let parameters = TrainingParameters {
+            restart,
+            ..TrainingParameters::default()
+        };
+        train(
+            database,
+            &parameters,
+            Split::default(),
+            &TrainingLog::default(),
+        )
+    }
+
+    /// Solves the walk from user 0 as a linear system, with users first and articles after them.
+    fn solve(restart: f64) -> DVector<f64> {
+        let mut transitions = DMatrix::zeros(7, 7);
+        for (user, article) in [(0, 0), (0, 1), (1, 1), (1, 2)] {
+            let (user_degree, article_degree) = ([2.0, 2.0][user], [1.0, 2.0, 1.0][article]);
+            transitions[(3 + article, user)] = 1.0 / user_degree;
+            transitions[(user, 3 + article)] = 1.0 / article_degree;
+        }
+        let start = DVector::from_vec(vec![0.0, 0.0, 0.0, 0.5, 0.5, 0.0, 0.0]);
+        // the walker at user 2 or article 3 has nowhere to go and jumps back to the start
+        for dangling in [2, 6] {
+            transitions.set_column(dangling, &start);
+        }
+        let system = DMatrix::identity(7, 7) - (1.0 - restart) * transitions;
+        system.lu().solve(&(restart * start)).unwrap()
+    }
+
+    #[test]
+    fn walks_converge_to_the_solution() {
+        for restart in [0.15, 0.3, 1.0] {
+            let model = model(restart);
+            let expected = solve(restart);
+            let start = DVector::from_vec(vec![1.0, 1.0, 0.0, 0.0]);
+            let [users, articles] = model.walk(DVector::zeros(3), start);
+            assert!((users.sum() + articles.sum() - 1.0).abs() < 1e-9);
+            for user_id in 0..3 {
+                assert!((users[user_id] - expected[user_id]).abs() < 1e-7);
+            }
+            for article_id in 0..4 {
+                assert!((articles[article_id] - expected[3 + article_id]).abs() < 1e-7);
+            }
+            assert_eq!(model.walk_from_user(0), articles);
+        }
+    }
+
+    #[test]
+    fn walks_with_a_small_restart_converge() {
+        let model = model(0.01);
+        let expected = solve(0.01);
+        let [users, articles] = model.walk(
+            DVector::zeros(3),
+            DVector::from_vec(vec![1.0, 1.0, 0.0, 0.0]),
+        );
+        // the walk stops once a step changes less than the tolerance, which leaves it up to
+        // `(1 - restart) / restart` times the tolerance away from the solution
+        for user_id in 0..3 {
+            assert!((users[user_id] - expected[user_id]).abs() < 1e-5);
+        }
+        for article_id in 0..4 {
+            assert!((articles[article_id] - expected[3 + article_id]).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn symmetric_users_get_mirrored_predictions() {
+        let model = model(0.3);
+        let (first, second) = (model.walk_from_user(0), model.walk_from_user(1));
+        assert!((first[0] - second[2]).abs() < 1e-9);
+        assert!((first[1] - second[1]).abs() < 1e-9);
+        assert!(first[0] > first[2]);
+    }
+
+    #[test]
+    fn single_predictions_match_the_walks() {
+        let model = model(0.3);
+        let walks = (0..3)
+            .map(|user_id| model.walk_from_user(user_id))
+            .collect::<Vec<_>>();
+        for (user_id, article_id) in [(0, 1), (0, 2), (1, 0), (0, 3), (2, 3), (2, 0)] {
+            assert_eq!(
+                model.predict(user_id, article_id),
+                walks[user_id][article_id]
+            );
+        }
+        let copy = model.clone();
+        assert_eq!(copy.predict(1, 2), walks[1][2]);
+    }
+}
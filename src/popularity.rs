@@ -0,0 +1,90 @@
+use crate::database::Database;
+use crate::evaluation::Split;
+use crate::recommender::{ModelRef, Recommender};
+use crate::report::TrainingLog;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// The number of votes of the global mean vote every article starts with. Articles with only a
+/// few votes are predicted close to the global mean instead of their few votes deciding alone.
+const DAMPING: f64 = 10.0;
+
+/// A non-personalized baseline, which predicts the same vote for every user: the mean vote of
+/// the article, damped towards the global mean vote. Personalized models should beat it, and it
+/// gives a blend of models a sense of what is generally liked.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct PopularityModel {
+    /// The database the model was trained on, shared with the other members of an ensemble.
+    pub(crate) database: Arc<Database>,
+
+    /// The damped mean vote of each article, indexed by article id.
+    article_means: Vec<f64>,
+
+    #[serde(default)]
+    held_out: Split,
+
+    /// The ids of the articles each user voted on, sorted. They are derived from the database and
+    /// therefore not stored.
+    #[serde(skip)]
+    user_votes: Vec<Vec<usize>>,
+}
+
+impl PopularityModel {
+    /// Recomputes the voted articles of all users from the database the model was trained on.
+    pub(crate) fn aggregate(&mut self) {
+        self.user_votes = self.database.user_votes();
+    }
+}
+
+impl Recommender for PopularityModel {
+    fn database(&self) -> &Database {
+        &self.database
+    }
+
+    fn held_out(&self) -> &Split {
+        &self.held_out
+    }
+
+    fn voted_articles(&self, user_id: usize) -> &[usize] {
+        &self.user_votes[user_id]
+    }
+
+    fn predict(&self, _user_id: usize, article_id: usize) -> f64 {
+        self.article_means[article_id]
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        serde_cbor::to_vec(&ModelRef::Popularity(self)).unwrap()
+    }
+}
+
+/// Trains the popularity baseline by averaging the votes of every article.
+pub(crate) fn train(database: Database, held_out: Split, log: &TrainingLog) -> PopularityModel {
+    let global_mean = database.mean_vote().unwrap_or(0.0);
+    let article_means = database
+        .article_votes()
+        .iter()
+        .map(|votes| {
+            let sum = votes
+                .iter()
+                .map(|&(_, vote)| if vote { 1.0 } else { -1.0 })
+                .sum::<f64>();
+            (sum + DAMPING * global_mean) / (votes.len() as f64 + DAMPING)
+        })
+        .collect();
+    if log.verbose {
+        println!(
+            "Averaged the votes of {} articles.",
+            database.article_count()
+        );
+    }
+
+    let mut model = PopularityModel {
+        database: Arc::new(database),
+        article_means,
+        held_out,
+        user_votes: Vec::new(),
+    };
+    model.aggregate();
+    model
+}
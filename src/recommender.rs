@@ -0,0 +1,302 @@
+use crate::database::{Database, FactorModel};
+use crate::ensemble::EnsembleModel;
+use crate::evaluation::Split;
+use crate::knn::NeighborhoodModel;
+use crate::pagerank::PageRankModel;
+use crate::popularity::PopularityModel;
+use crate::storage::Storage;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// A trained model that predicts the votes of users for articles. All kinds of prediction models
+/// implement it, so the commands that query or evaluate a model work with all of them.
+pub(crate) trait Recommender: Sync {
+    /// The database the model was trained on. If votes were held out, they are not part of it.
+    fn database(&self) -> &Database;
+
+    /// The votes that were held out from training.
+    fn held_out(&self) -> &Split;
+
+    /// The ids of all articles a user has voted on in the training data, in ascending order.
+    fn voted_articles(&self, user_id: usize) -> &[usize];
+
+    /// Predicts the vote of a user for an article.
+    fn predict(&self, user_id: usize, article_id: usize) -> f64;
+
+    /// Predicts the votes of a user for several articles. Models that compute the predictions
+    /// of a user for all articles at once override it.
+    fn predict_articles(&self, user_id: usize, article_ids: &[usize]) -> Vec<f64> {
+        article_ids
+            .iter()
+            .map(|&article_id| self.predict(user_id, article_id))
+            .collect()
+    }
+
+    /// Predicts the votes of several users for an article. Models that compute the predictions
+    /// for an article of all users at once override it.
+    fn predict_users(&self, article_id: usize, user_ids: &[usize]) -> Vec<f64> {
+        user_ids
+            .iter()
+            .map(|&user_id| self.predict(user_id, article_id))
+            .collect()
+    }
+
+    /// Serializes the model into the format of the model file, tagged with the kind of the model
+    /// so that [`Model::load`] can restore it.
+    fn serialize(&self) -> Vec<u8>;
+
+    /// Describes a predicted vote for the console.
+    fn describe_prediction(&self, prediction: f64) -> String {
+        format!("predicted vote: {:.2}", prediction)
+    }
+
+    /// Explains the recommendations for a user for the console, if the model can.
+    fn explain(&self, _user_id: usize) -> Option<String> {
+        None
+    }
+
+    /// Returns the `k` articles with the highest predicted votes of the user among those the user
+    /// has not voted on in the training data, best first, with their predicted votes.
+    fn top_articles(&self, user_id: usize, k: usize) -> Vec<(usize, f64)> {
+        let voted = self.voted_articles(user_id);
+        let article_ids = (0..self.database().article_count())
+            .filter(|article_id| voted.binary_search(article_id).is_err())
+            .collect::<Vec<_>>();
+        let predictions = self.predict_articles(user_id, &article_ids);
+        best(article_ids.into_iter().zip(predictions).collect(), k)
+    }
+
+    /// Returns the `k` users with the highest predicted votes for the article among those who
+    /// have not voted on it in the training data, best first, with their predicted votes.
+    fn top_users(&self, article_id: usize, k: usize) -> Vec<(usize, f64)> {
+        let mut voted = vec![false; self.database().user_count()];
+        for &(user_id, _) in &self.database().article_votes()[article_id] {
+            voted[user_id] = true;
+        }
+        let user_ids = (0..voted.len())
+            .filter(|&user_id| !voted[user_id])
+            .collect::<Vec<_>>();
+        let predictions = self.predict_users(article_id, &user_ids);
+        best(user_ids.into_iter().zip(predictions).collect(), k)
+    }
+
+    /// Saves the model to the model file configured in the [`Storage`]. The previous model file is
+    /// kept as a backup.
+    fn save(&self, storage: &Storage) {
+        storage
+            .write(&storage.model_path, &self.serialize())
+            .expect("Failed to write prediction model to file.");
+    }
+
+    /// Predicts the votes of a user for all articles and reports the `top` predictions to the
+    /// console.
+    fn predict_for_user(&self, name: &str, top: usize) {
+        let Some(user_id) = self.database().user_id(name) else {
+            println!("User {} not found.", name);
+            return;
+        };
+
+        let names = self.database().article_names();
+        print!("User {} will most likely upvote those articles: ", name);
+        for (article_id, prediction) in self.top_articles(user_id, top) {
+            print!(
+                "{} ({}), ",
+                names[article_id],
+                self.describe_prediction(prediction)
+            );
+        }
+        println!();
+        if let Some(explanation) = self.explain(user_id) {
+            println!("{}", explanation);
+        }
+    }
+
+    /// Predicts the votes of all users for a given article and reports the `top` predictions to
+    /// the console.
+    fn predict_for_article(&self, name: &str, top: usize) {
+        let Some(article_id) = self.database().article_id(name) else {
+            println!("Article not found.");
+            return;
+        };
+
+        let names = self.database().user_names();
+        print!("{} will most likely be upvoted by: ", name);
+        for (user_id, prediction) in self.top_users(article_id, top) {
+            println!(
+                "{} ({}), ",
+                names[user_id],
+                self.describe_prediction(prediction)
+            );
+        }
+        println!();
+    }
+}
+
+/// Keeps the `k` candidates with the highest predicted votes, best first.
+fn best(mut candidates: Vec<(usize, f64)>, k: usize) -> Vec<(usize, f64)> {
+    let by_prediction = |a: &(usize, f64), b: &(usize, f64)| b.1.total_cmp(&a.1);
+    if candidates.len() > k {
+        candidates.select_nth_unstable_by(k, by_prediction);
+        candidates.truncate(k);
+    }
+    candidates.sort_by(by_prediction);
+    candidates
+}
+
+/// A prediction model of any kind, as stored in the model file. The model file is tagged with
+/// the kind of the model, so it can be loaded without knowing which kind was trained.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) enum Model {
+    Factors(FactorModel),
+    Neighborhood(NeighborhoodModel),
+    Popularity(PopularityModel),
+    Ensemble(EnsembleModel),
+    PageRank(PageRankModel),
+}
+
+/// A borrowed [`Model`], which is serialized in the same format without copying the model.
+#[derive(Serialize)]
+pub(crate) enum ModelRef<'a> {
+    Factors(&'a FactorModel),
+    Neighborhood(&'a NeighborhoodModel),
+    Popularity(&'a PopularityModel),
+    Ensemble(&'a EnsembleModel),
+    PageRank(&'a PageRankModel),
+}
+
+impl Model {
+    /// Loads the prediction model from the model file configured in the [`Storage`].
+    pub(crate) fn load(storage: &Storage) -> Self {
+        let buffer = storage
+            .read(&storage.model_path)
+            .expect("Failed to read prediction model from file.");
+        let mut model = serde_cbor::from_slice(&buffer)
+            .ok()
+            .or_else(|| Self::legacy(&buffer))
+            .expect("Failed to parse prediction model, models of older versions may have to be trained again.");
+        model.aggregate();
+        model
+    }
+
+    /// Parses a model file written before model files were tagged, which always holds a factor
+    /// model.
+    fn legacy(buffer: &[u8]) -> Option<Self> {
+        serde_cbor::from_slice(buffer).ok().map(Model::Factors)
+    }
+
+    /// Recomputes the values that are derived from the stored ones after loading the model.
+    pub(crate) fn aggregate(&mut self) {
+        match self {
+            Model::Factors(model) => model.aggregate(),
+            Model::Neighborhood(model) => model.aggregate(),
+            Model::Popularity(model) => model.aggregate(),
+            Model::Ensemble(model) => model.aggregate(),
+            Model::PageRank(model) => model.aggregate(),
+        }
+    }
+
+    /// Replaces the database the model was trained on, so that models trained on the same votes
+    /// can share it. The derived values have to be recomputed with [`Model::aggregate`] if the
+    /// votes changed.
+    pub(crate) fn set_database(&mut self, database: Arc<Database>) {
+        match self {
+            Model::Factors(model) => model.database = database,
+            Model::Neighborhood(model) => model.database = database,
+            Model::Popularity(model) => model.database = database,
+            Model::Ensemble(model) => model.database = database,
+            Model::PageRank(model) => model.database = database,
+        }
+    }
+
+    /// The model as a recommender of its kind.
+    fn inner(&self) -> &dyn Recommender {
+        match self {
+            Model::Factors(model) => model,
+            Model::Neighborhood(model) => model,
+            Model::Popularity(model) => model,
+            Model::Ensemble(model) => model,
+            Model::PageRank(model) => model,
+        }
+    }
+}
+
+impl Recommender for Model {
+    fn database(&self) -> &Database {
+        self.inner().database()
+    }
+
+    fn held_out(&self) -> &Split {
+        self.inner().held_out()
+    }
+
+    fn voted_articles(&self, user_id: usize) -> &[usize] {
+        self.inner().voted_articles(user_id)
+    }
+
+    fn predict(&self, user_id: usize, article_id: usize) -> f64 {
+        self.inner().predict(user_id, article_id)
+    }
+
+    fn predict_articles(&self, user_id: usize, article_ids: &[usize]) -> Vec<f64> {
+        self.inner().predict_articles(user_id, article_ids)
+    }
+
+    fn predict_users(&self, article_id: usize, user_ids: &[usize]) -> Vec<f64> {
+        self.inner().predict_users(article_id, user_ids)
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        self.inner().serialize()
+    }
+
+    fn describe_prediction(&self, prediction: f64) -> String {
+        self.inner().describe_prediction(prediction)
+    }
+
+    fn explain(&self, user_id: usize) -> Option<String> {
+        self.inner().explain(user_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::DMatrix;
+
+    /// A database with two users and three articles.
+    fn database() -> Database {
+        let mut database = Database::new();
+        database.add_user("a".to_string());
+        database.add_user("b".to_string());
+        for article in ["scp-1", "scp-2", "scp-3"] {
+            database.add_article(article.to_string(), String::new(), vec![(0, true)]);
+        }
+        database
+    }
+
+    /// The layout of the model file before model files were tagged.
+    #[derive(Serialize)]
+    struct PredictionModel {
+        database: Database,
+        user_factors: DMatrix<f64>,
+        article_factors: DMatrix<f64>,
+        user_votes: Vec<Vec<usize>>,
+    }
+
+    #[test]
+    fn legacy_factor_models_are_read() {
+        let database = database();
+        let legacy = PredictionModel {
+            user_votes: database.user_votes(),
+            database,
+            user_factors: DMatrix::from_element(2, 4, 0.1),
+            article_factors: DMatrix::from_element(3, 4, 0.1),
+        };
+        let buffer = serde_cbor::to_vec(&legacy).unwrap();
+        let Some(Model::Factors(mut model)) = Model::legacy(&buffer) else {
+            panic!("The legacy factor model was not read.");
+        };
+        model.aggregate();
+        assert!((model.predict(1, 2) - 0.04).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,483 @@
+use crate::database::TrainingParameters;
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The width of the terminal plot in characters, without the axis labels.
+const PLOT_WIDTH: usize = 72;
+
+/// The height of the terminal plot in lines.
+const PLOT_HEIGHT: usize = 20;
+
+/// The characters marking the curves of different runs in the terminal plot.
+const PLOT_MARKERS: &[char] = &['*', '+', 'o', 'x', '#', '@', '%', '&'];
+
+/// The colors of the curves of different runs in the SVG plot.
+const SVG_COLORS: &[&str] = &[
+    "#1f77b4", "#d62728", "#2ca02c", "#ff7f0e", "#9467bd", "#8c564b", "#e377c2", "#17becf",
+];
+
+/// Where training reports its progress. Messages go to the console if `verbose` is set. If a log
+/// file is open, every iteration is also written to it as one line of JSON (JSON Lines), after a
+/// first line with the training parameters. Each line is written as soon as the iteration has
+/// finished, so the log of an interrupted training is complete up to the interruption.
+#[derive(Debug, Default)]
+pub(crate) struct TrainingLog {
+    /// Whether progress messages are printed to the console.
+    pub(crate) verbose: bool,
+
+    /// The log file the records are written to, if any.
+    file: Option<Mutex<File>>,
+}
+
+/// The progress of one training iteration, as written to the training log.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Record {
+    /// The factor being learned, for the algorithms that learn one factor after another.
+    pub(crate) factor: Option<usize>,
+
+    /// The iteration, counted from 0 within the factor or the whole training.
+    pub(crate) iteration: usize,
+
+    /// The loss on the training votes: the mean square error of the predicted votes, or the
+    /// negative mean log-likelihood of the ranked pairs for BPR.
+    pub(crate) training_loss: f64,
+
+    /// The mean square error on the validation votes, if votes are held out for validation.
+    pub(crate) validation_loss: Option<f64>,
+
+    /// The norm of the full-batch gradient, for the algorithms that compute one.
+    pub(crate) gradient_norm: Option<f64>,
+
+    /// How long the iteration took.
+    pub(crate) duration: Duration,
+}
+
+impl TrainingLog {
+    /// Creates a log that reports to the console if `verbose` is set and writes no file.
+    pub(crate) fn new(verbose: bool) -> Self {
+        Self {
+            verbose,
+            file: None,
+        }
+    }
+
+    /// Creates a log that also writes its records to the file at `path`, creating its directory
+    /// if needed. The file is replaced, unless `append` is set, which continues the log of a
+    /// resumed training. The parameters are written first if the file is empty.
+    pub(crate) fn with_file(
+        verbose: bool,
+        path: &Path,
+        parameters: &TrainingParameters,
+        append: bool,
+    ) -> io::Result<Self> {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)?;
+        if file.metadata()?.len() == 0 {
+            let header = json::object! { parameters: parameters_json(parameters) };
+            writeln!(file, "{}", header.dump())?;
+        }
+
+        Ok(Self {
+            verbose,
+            file: Some(Mutex::new(file)),
+        })
+    }
+
+    /// Whether records are written to a file. Trainers only compute values for the log that
+    /// they do not need otherwise, like the validation loss without early stopping, if it is.
+    pub(crate) fn is_recording(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Writes the record to the log file, if there is one.
+    pub(crate) fn record(&self, record: Record) {
+        let Some(file) = &self.file else {
+            return;
+        };
+
+        let line = json::object! {
+            factor: record.factor,
+            iteration: record.iteration,
+            training_loss: record.training_loss,
+            validation_loss: record.validation_loss,
+            gradient_norm: record.gradient_norm,
+            milliseconds: record.duration.as_secs_f64() * 1000.0,
+        };
+        writeln!(file.lock().unwrap(), "{}", line.dump())
+            .expect("Failed to write to training log.");
+    }
+}
+
+/// Converts the training parameters into the JSON object at the start of a training log.
+fn parameters_json(parameters: &TrainingParameters) -> json::JsonValue {
+    json::object! {
+        model: format!("{:?}", parameters.model),
+        algorithm: format!("{:?}", parameters.algorithm),
+        latent_factors: parameters.latent_factors,
+        iterations: parameters.iterations,
+        learning_rate: parameters.learning_rate,
+        optimizer: format!("{:?}", parameters.optimizer),
+        precision: format!("{:?}", parameters.precision),
+        regularization: parameters.regularization,
+        user_regularization: parameters.user_regularization,
+        article_regularization: parameters.article_regularization,
+        bias_regularization: parameters.bias_regularization,
+        regularization_scaling: format!("{:?}", parameters.regularization_scaling),
+        biases: parameters.biases,
+        loss: format!("{:?}", parameters.loss),
+        confidence: parameters.confidence,
+        negative_sampling: format!("{:?}", parameters.negative_sampling),
+        initialization: format!("{:?}", parameters.initialization()),
+        propensity: format!("{:?}", parameters.propensity),
+        similarity: format!("{:?}", parameters.similarity),
+        neighbors: parameters.neighbors,
+        restart: parameters.restart,
+        seed: parameters.seed,
+        deterministic: parameters.deterministic,
+        early_stopping: parameters.early_stopping.map(|early_stopping| early_stopping.patience),
+        rank_selection: parameters.rank_selection,
+    }
+}
+
+/// Which loss of the training logs is plotted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Curve {
+    Training,
+    Validation,
+}
+
+/// A training run read back from its log.
+#[derive(Clone, Debug)]
+pub(crate) struct Run {
+    /// The name of the run in the legend, the file name of its log.
+    pub(crate) name: String,
+
+    /// A short description of the training parameters.
+    pub(crate) description: String,
+
+    /// The training loss of every iteration, in the order of the log.
+    pub(crate) training_loss: Vec<f64>,
+
+    /// The validation loss of every iteration, if it was recorded.
+    pub(crate) validation_loss: Vec<Option<f64>>,
+
+    /// The total training time in milliseconds.
+    pub(crate) milliseconds: f64,
+
+    /// The number of lines that could not be parsed, like the last line of a log whose training
+    /// was killed while writing it.
+    pub(crate) skipped_lines: usize,
+}
+
+impl Run {
+    /// Reads the training log at `path`. Lines that cannot be parsed are skipped and counted.
+    pub(crate) fn load(path: &Path) -> Self {
+        let contents = fs::read_to_string(path).expect("Failed to read training log.");
+        let mut run = Run {
+            name: path.file_stem().map_or_else(
+                || path.display().to_string(),
+                |name| name.to_string_lossy().into_owned(),
+            ),
+            description: String::new(),
+            training_loss: Vec::new(),
+            validation_loss: Vec::new(),
+            milliseconds: 0.0,
+            skipped_lines: 0,
+        };
+
+        for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+            let Ok(value) = json::parse(line) else {
+                run.skipped_lines += 1;
+                continue;
+            };
+            let parameters = &value["parameters"];
+            if parameters.is_object() {
+                run.description = format!(
+                    "{}, {} factors, {} iterations, learning rate {}, regularization {}",
+                    parameters["algorithm"],
+                    parameters["latent_factors"],
+                    parameters["iterations"],
+                    parameters["learning_rate"],
+                    parameters["regularization"]
+                );
+                continue;
+            }
+
+            let Some(training_loss) = value["training_loss"].as_f64() else {
+                run.skipped_lines += 1;
+                continue;
+            };
+            run.training_loss.push(training_loss);
+            run.validation_loss.push(value["validation_loss"].as_f64());
+            run.milliseconds += value["milliseconds"].as_f64().unwrap_or(0.0);
+        }
+        run
+    }
+
+    /// The plotted loss of every iteration. Iterations without a value are skipped.
+    fn points(&self, curve: Curve) -> Vec<(usize, f64)> {
+        match curve {
+            Curve::Training => self.training_loss.iter().copied().enumerate().collect(),
+            Curve::Validation => self
+                .validation_loss
+                .iter()
+                .enumerate()
+                .filter_map(|(iteration, loss)| loss.map(|loss| (iteration, loss)))
+                .collect(),
+        }
+    }
+
+    /// Summarizes the run: its final training loss, its best validation loss and its time.
+    pub(crate) fn summary(&self) -> String {
+        let mut summary = format!("{}: {}", self.name, self.description);
+        if let Some(loss) = self.training_loss.last() {
+            write!(
+                summary,
+                "\n  {} iterations in {:.0}ms, final training loss {:.4}",
+                self.training_loss.len(),
+                self.milliseconds,
+                loss
+            )
+            .unwrap();
+        }
+        let best = self
+            .points(Curve::Validation)
+            .into_iter()
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((iteration, loss)) = best {
+            write!(
+                summary,
+                ", best validation loss {:.4} at iteration {}",
+                loss,
+                iteration + 1
+            )
+            .unwrap();
+        }
+        if self.skipped_lines > 0 {
+            write!(
+                summary,
+                "\n  skipped {} lines that could not be parsed",
+                self.skipped_lines
+            )
+            .unwrap();
+        }
+        summary
+    }
+}
+
+/// The ranges of all plotted points: the number of iterations and the lowest and highest loss.
+/// Returns `None` if there is nothing to plot.
+fn bounds(runs: &[Run], curve: Curve) -> Option<(usize, f64, f64)> {
+    let points = runs
+        .iter()
+        .flat_map(|run| run.points(curve))
+        .collect::<Vec<_>>();
+    if points.is_empty() {
+        return None;
+    }
+
+    let iterations = points
+        .iter()
+        .map(|&(iteration, _)| iteration)
+        .max()
+        .unwrap()
+        + 1;
+    let low = points
+        .iter()
+        .map(|&(_, loss)| loss)
+        .fold(f64::INFINITY, f64::min);
+    let high = points
+        .iter()
+        .map(|&(_, loss)| loss)
+        .fold(f64::NEG_INFINITY, f64::max);
+    // a flat curve still needs a range to be drawn
+    let high = if high > low { high } else { low + 1.0 };
+    Some((iterations, low, high))
+}
+
+/// Renders the loss curves of the runs as a plot of characters for the terminal, with one marker
+/// per run. Returns `None` if no run has values for the curve.
+pub(crate) fn plot_terminal(runs: &[Run], curve: Curve) -> Option<String> {
+    let (iterations, low, high) = bounds(runs, curve)?;
+
+    let mut grid = vec![vec![' '; PLOT_WIDTH]; PLOT_HEIGHT];
+    for (index, run) in runs.iter().enumerate() {
+        let marker = PLOT_MARKERS[index % PLOT_MARKERS.len()];
+        for (iteration, loss) in run.points(curve) {
+            let column = iteration * (PLOT_WIDTH - 1) / (iterations - 1).max(1);
+            let row = ((high - loss) / (high - low) * (PLOT_HEIGHT - 1) as f64).round() as usize;
+            grid[row][column] = marker;
+        }
+    }
+
+    let mut plot = String::new();
+    for (row, line) in grid.iter().enumerate() {
+        let label = match row {
+            0 => format!("{:>10.4}", high),
+            _ if row == PLOT_HEIGHT - 1 => format!("{:>10.4}", low),
+            _ => " ".repeat(10),
+        };
+        writeln!(
+            plot,
+            "{} |{}",
+            label,
+            line.iter().collect::<String>().trim_end()
+        )
+        .unwrap();
+    }
+    writeln!(plot, "{} +{}", " ".repeat(10), "-".repeat(PLOT_WIDTH)).unwrap();
+    writeln!(
+        plot,
+        "{} 1{:>width$}",
+        " ".repeat(11),
+        iterations,
+        width = PLOT_WIDTH - 1
+    )
+    .unwrap();
+    for (index, run) in runs.iter().enumerate() {
+        writeln!(
+            plot,
+            "  {} {}",
+            PLOT_MARKERS[index % PLOT_MARKERS.len()],
+            run.name
+        )
+        .unwrap();
+    }
+    Some(plot)
+}
+
+/// Writes the loss curves of the runs as an SVG image to `path`, with one colored line per run.
+/// The image is an export rather than data of the program, so an existing file is simply
+/// replaced without backup. Returns `false` without writing anything if no run has values for the
+/// curve.
+pub(crate) fn write_svg(path: &Path, runs: &[Run], curve: Curve) -> io::Result<bool> {
+    let Some((iterations, low, high)) = bounds(runs, curve) else {
+        return Ok(false);
+    };
+
+    let (width, height) = (800.0, 480.0);
+    let (left, right, top, bottom) = (70.0, 20.0, 40.0, 50.0);
+    let legend = 20.0 * runs.len() as f64;
+    let plot_width = width - left - right;
+    let plot_height = height - top - bottom;
+    let x =
+        |iteration: usize| left + iteration as f64 * plot_width / (iterations - 1).max(1) as f64;
+    let y = |loss: f64| top + (high - loss) / (high - low) * plot_height;
+
+    let title = match curve {
+        Curve::Training => "Training loss",
+        Curve::Validation => "Validation loss",
+    };
+    let mut svg = String::new();
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-family="sans-serif" font-size="12">"#,
+        width,
+        height + legend
+    )
+    .unwrap();
+    writeln!(svg, r#"<rect width="100%" height="100%" fill="white"/>"#).unwrap();
+    writeln!(
+        svg,
+        r#"<text x="{}" y="24" text-anchor="middle" font-size="16">{}</text>"#,
+        width / 2.0,
+        title
+    )
+    .unwrap();
+
+    // axes with the range of the values at their ends
+    writeln!(
+        svg,
+        r#"<polyline points="{left},{top} {left},{} {},{}" fill="none" stroke="black"/>"#,
+        top + plot_height,
+        left + plot_width,
+        top + plot_height
+    )
+    .unwrap();
+    for (loss, anchor_y) in [(high, top + 4.0), (low, top + plot_height)] {
+        writeln!(
+            svg,
+            r#"<text x="{}" y="{}" text-anchor="end">{:.4}</text>"#,
+            left - 6.0,
+            anchor_y,
+            loss
+        )
+        .unwrap();
+    }
+    for (iteration, anchor) in [(0, "start"), (iterations - 1, "end")] {
+        writeln!(
+            svg,
+            r#"<text x="{}" y="{}" text-anchor="{}">{}</text>"#,
+            x(iteration),
+            top + plot_height + 18.0,
+            anchor,
+            iteration + 1
+        )
+        .unwrap();
+    }
+    writeln!(
+        svg,
+        r#"<text x="{}" y="{}" text-anchor="middle">iteration</text>"#,
+        left + plot_width / 2.0,
+        top + plot_height + 18.0
+    )
+    .unwrap();
+
+    for (index, run) in runs.iter().enumerate() {
+        let color = SVG_COLORS[index % SVG_COLORS.len()];
+        let points = run
+            .points(curve)
+            .into_iter()
+            .map(|(iteration, loss)| format!("{:.1},{:.1}", x(iteration), y(loss)))
+            .collect::<Vec<_>>()
+            .join(" ");
+        writeln!(
+            svg,
+            r#"<polyline points="{}" fill="none" stroke="{}" stroke-width="1.5"/>"#,
+            points, color
+        )
+        .unwrap();
+
+        let legend_y = height + 20.0 * index as f64;
+        writeln!(
+            svg,
+            r#"<line x1="{left}" y1="{}" x2="{}" y2="{}" stroke="{}" stroke-width="3"/>"#,
+            legend_y - 4.0,
+            left + 20.0,
+            legend_y - 4.0,
+            color
+        )
+        .unwrap();
+        writeln!(
+            svg,
+            r#"<text x="{}" y="{}">{}: {}</text>"#,
+            left + 28.0,
+            legend_y,
+            escape(&run.name),
+            escape(&run.description)
+        )
+        .unwrap();
+    }
+    svg.push_str("</svg>\n");
+
+    fs::write(path, svg)?;
+    Ok(true)
+}
+
+/// Escapes text for use in SVG.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
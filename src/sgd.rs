@@ -0,0 +1,275 @@
+use crate::database::{Biases, Database, EarlyStopping, FactorModel, TrainingParameters};
+use crate::evaluation::Split;
+use crate::hogwild::SharedMatrix;
+use crate::optimizer::SharedMoments;
+use crate::report::{Record, TrainingLog};
+use nalgebra::DMatrix;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rayon::iter::ParallelIterator;
+use rayon::slice::ParallelSlice;
+
+/// The number of votes each parallel task trains on in a row.
+const CHUNK_SIZE: usize = 4096;
+
+/// Trains a factor model with stochastic gradient descent. Every iteration visits all votes in a
+/// new random order and updates all factors of the vote's user and article right away, instead of
+/// accumulating a gradient over all votes and learning one factor after another. The shuffled
+/// votes are split into chunks that are trained in parallel without locking, in the Hogwild
+/// style: concurrent updates of the same parameters may overwrite each other, which is rare
+/// enough for sparse votes not to hurt. Deterministic training visits the chunks one after
+/// another instead.
+pub(crate) fn train(
+    database: Database,
+    parameters: &TrainingParameters,
+    held_out: Split,
+    log: &TrainingLog,
+) -> FactorModel {
+    let TrainingParameters {
+        latent_factors,
+        iterations,
+        learning_rate,
+        optimizer,
+        regularization_scaling,
+        biases: learn_biases,
+        loss,
+        propensity,
+        seed,
+        deterministic,
+        early_stopping,
+        ..
+    } = *parameters;
+    let initialization = parameters.initialization();
+
+    let mut votes = database
+        .article_votes()
+        .iter()
+        .enumerate()
+        .flat_map(|(article_id, votes)| {
+            votes
+                .iter()
+                .map(move |&(user_id, vote)| (user_id, article_id, vote))
+        })
+        .collect::<Vec<_>>();
+    let weights = database.propensity_weights(propensity);
+
+    // Every visit of a vote regularizes its user and article by their share of the scaled
+    // regularization, so that one pass over all votes applies it in full.
+    let (user_penalty, article_penalty) = (parameters.user_penalty(), parameters.article_penalty());
+    let mut user_counts = vec![0; database.user_count()];
+    for &(user_id, _, _) in &votes {
+        user_counts[user_id] += 1;
+    }
+    let user_shares = user_counts
+        .into_iter()
+        .map(|count| regularization_scaling.share(count))
+        .collect::<Vec<_>>();
+    let article_shares = database
+        .article_votes()
+        .iter()
+        .map(|votes| regularization_scaling.share(votes.len()))
+        .collect::<Vec<_>>();
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let user_factors =
+        SharedMatrix::new(&initialization.matrix(database.user_count(), latent_factors, &mut rng));
+    let article_factors = SharedMatrix::new(&initialization.matrix(
+        database.article_count(),
+        latent_factors,
+        &mut rng,
+    ));
+    let user_biases = SharedMatrix::new(&DMatrix::zeros(database.user_count(), 1));
+    let article_biases = SharedMatrix::new(&DMatrix::zeros(database.article_count(), 1));
+    let user_moments = SharedMoments::new(optimizer, database.user_count(), latent_factors);
+    let article_moments = SharedMoments::new(optimizer, database.article_count(), latent_factors);
+    let user_bias_moments = SharedMoments::new(optimizer, database.user_count(), 1);
+    let article_bias_moments = SharedMoments::new(optimizer, database.article_count(), 1);
+
+    // without learned biases, they stay zero and do not affect the predictions
+    let global_mean = if learn_biases {
+        loss.score(database.mean_vote().unwrap_or(0.0))
+    } else {
+        0.0
+    };
+    let score = |user_id: usize, article_id: usize| {
+        let mut score =
+            global_mean + user_biases.get(user_id, 0) + article_biases.get(article_id, 0);
+        for factor in 0..latent_factors {
+            score += user_factors.get(user_id, factor) * article_factors.get(article_id, factor);
+        }
+        score
+    };
+
+    // the lowest validation error together with the parameters achieving it
+    let mut best_validation_error = f64::INFINITY;
+    let mut best_state = None;
+    let mut iterations_without_improvement = 0;
+
+    for iteration in 0..iterations {
+        let now = std::time::Instant::now();
+        votes.shuffle(&mut rng);
+
+        let train_chunk = |chunk: &[(usize, usize, bool)]| {
+            let mut user = vec![0.0; latent_factors];
+            let mut article = user.clone();
+            let mut square_error = 0.0;
+
+            for &(user_id, article_id, vote) in chunk {
+                let vote = if vote { 1.0 } else { -1.0 };
+                let score = score(user_id, article_id);
+                let weight = weights[article_id];
+                let error = weight * loss.error(vote, score);
+                square_error += weight * (vote - loss.vote(score)).powi(2);
+
+                let user_share = user_shares[user_id];
+                let article_share = article_shares[article_id];
+
+                user_factors.read_row(user_id, &mut user);
+                article_factors.read_row(article_id, &mut article);
+                for factor in 0..latent_factors {
+                    let (u, a) = (user[factor], article[factor]);
+                    user_factors.add(
+                        user_id,
+                        factor,
+                        user_moments.update(
+                            learning_rate,
+                            user_id,
+                            factor,
+                            error * a - user_penalty.factors * user_share * u,
+                        ),
+                    );
+                    article_factors.add(
+                        article_id,
+                        factor,
+                        article_moments.update(
+                            learning_rate,
+                            article_id,
+                            factor,
+                            error * u - article_penalty.factors * article_share * a,
+                        ),
+                    );
+                }
+                user_moments.finish_row(user_id);
+                article_moments.finish_row(article_id);
+                if learn_biases {
+                    let user_bias = user_biases.get(user_id, 0);
+                    let article_bias = article_biases.get(article_id, 0);
+                    user_biases.add(
+                        user_id,
+                        0,
+                        user_bias_moments.update(
+                            learning_rate,
+                            user_id,
+                            0,
+                            error - user_penalty.biases * user_share * user_bias,
+                        ),
+                    );
+                    article_biases.add(
+                        article_id,
+                        0,
+                        article_bias_moments.update(
+                            learning_rate,
+                            article_id,
+                            0,
+                            error - article_penalty.biases * article_share * article_bias,
+                        ),
+                    );
+                    user_bias_moments.finish_row(user_id);
+                    article_bias_moments.finish_row(article_id);
+                }
+            }
+            square_error
+        };
+        let square_error: f64 = if deterministic {
+            votes.chunks(CHUNK_SIZE).map(train_chunk).sum()
+        } else {
+            votes.par_chunks(CHUNK_SIZE).map(train_chunk).sum()
+        };
+
+        let duration = now.elapsed();
+        let training_error = square_error / votes.len() as f64;
+
+        if log.verbose {
+            println!(
+                "Iteration {}/{} finished in {}ms.",
+                iteration + 1,
+                iterations,
+                duration.as_millis()
+            );
+            println!("Mean square error: {}", training_error);
+        }
+
+        let validation_error = (early_stopping.is_some()
+            || log.is_recording() && held_out.has_validation())
+        .then(|| {
+            Database::mean_square_error(&held_out.validation, |user_id, article_id| {
+                loss.vote(score(user_id, article_id))
+            })
+        })
+        .flatten();
+        log.record(Record {
+            factor: None,
+            iteration,
+            training_loss: training_error,
+            validation_loss: validation_error,
+            gradient_norm: None,
+            duration,
+        });
+
+        if let (Some(EarlyStopping { patience, .. }), Some(validation_error)) =
+            (early_stopping, validation_error)
+        {
+            if log.verbose {
+                println!("Validation mean square error: {}", validation_error);
+            }
+            if validation_error < best_validation_error {
+                best_validation_error = validation_error;
+                best_state = Some([
+                    user_factors.to_matrix(),
+                    article_factors.to_matrix(),
+                    user_biases.to_matrix(),
+                    article_biases.to_matrix(),
+                ]);
+                iterations_without_improvement = 0;
+            } else {
+                iterations_without_improvement += 1;
+                if iterations_without_improvement >= patience {
+                    if log.verbose {
+                        println!("Validation error stopped improving, stopping training.");
+                    }
+                    break;
+                }
+            }
+        }
+    }
+
+    if log.verbose {
+        println!("Training finished.");
+    }
+
+    // restore the parameters with the lowest validation error
+    let [user_factors, article_factors, user_biases, article_biases] =
+        best_state.unwrap_or_else(|| {
+            [
+                user_factors.to_matrix(),
+                article_factors.to_matrix(),
+                user_biases.to_matrix(),
+                article_biases.to_matrix(),
+            ]
+        });
+
+    let biases = learn_biases.then(|| Biases {
+        global_mean,
+        user: user_biases.column(0).into_owned(),
+        article: article_biases.column(0).into_owned(),
+    });
+    FactorModel::new(
+        database,
+        user_factors,
+        article_factors,
+        biases,
+        loss,
+        held_out,
+    )
+}
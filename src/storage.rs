@@ -0,0 +1,188 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Default number of backup generations kept for every saved file.
+pub(crate) const DEFAULT_BACKUPS: usize = 3;
+
+/// Environment variable that sets the data directory if it is not given on the command line.
+pub(crate) const DATA_DIR_VARIABLE: &str = "GEYSER_DATA_DIR";
+
+/// Location and backup policy of the files the bot reads and writes. Files are never overwritten
+/// in place: new contents are written to a temporary file, synced to disk and then atomically
+/// renamed over the old file, so a crash mid-write leaves the previous version intact. The
+/// previous version itself is kept as a numbered backup generation (`database.bin.1` being the
+/// most recent one).
+#[derive(Clone, Debug)]
+pub(crate) struct Storage {
+    /// Directory for all files that have no explicitly configured path.
+    data_dir: PathBuf,
+
+    /// Path of the vote database.
+    pub(crate) database_path: PathBuf,
+
+    /// Path of the trained prediction model.
+    pub(crate) model_path: PathBuf,
+
+    /// Path of the checkpoint of an unfinished training.
+    pub(crate) checkpoint_path: PathBuf,
+
+    /// How many previous generations of each file are kept when it is overwritten.
+    backups: usize,
+}
+
+impl Storage {
+    /// Creates a storage configuration keeping the given number of backup generations. All files
+    /// are placed in `data_dir`. If no directory is given, the directory from the
+    /// `GEYSER_DATA_DIR` environment variable is used, and if that is unset, the working
+    /// directory.
+    pub(crate) fn new(data_dir: Option<PathBuf>, backups: usize) -> Self {
+        let data_dir = data_dir
+            .or_else(|| std::env::var_os(DATA_DIR_VARIABLE).map(PathBuf::from))
+            .unwrap_or_default();
+
+        Self {
+            database_path: data_dir.join("database.bin"),
+            model_path: data_dir.join("prediction_model.bin"),
+            checkpoint_path: data_dir.join("checkpoint.bin"),
+            data_dir,
+            backups,
+        }
+    }
+
+    /// Path of a file with the given name in the data directory.
+    pub(crate) fn data_path(&self, name: &str) -> PathBuf {
+        self.data_dir.join(name)
+    }
+
+    /// Reads the whole file at `path`.
+    pub(crate) fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let mut file = File::open(path)?;
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Atomically replaces the file at `path` with `data`. The current contents of the file (if
+    /// any) are rotated into the backup generations before the new file is moved into place.
+    pub(crate) fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let temporary_path = Self::write_temporary(path, data)?;
+        if path.exists() {
+            self.rotate(path)?;
+        }
+
+        fs::rename(&temporary_path, path)?;
+        Self::sync_directory(path);
+        Ok(())
+    }
+
+    /// Atomically replaces the file at `path` with `data` without keeping backups. Used for files
+    /// that are rewritten often and are worthless once outdated.
+    pub(crate) fn replace(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let temporary_path = Self::write_temporary(path, data)?;
+        fs::rename(&temporary_path, path)?;
+        Self::sync_directory(path);
+        Ok(())
+    }
+
+    /// Deletes the file at `path` if it exists. Backups are kept.
+    pub(crate) fn remove(&self, path: &Path) -> io::Result<()> {
+        match fs::remove_file(path) {
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Rolls the file at `path` back to the given backup generation. The replaced contents are
+    /// rotated into the backups like on every other write, so a restore can itself be undone by
+    /// restoring generation 1.
+    pub(crate) fn restore(&self, path: &Path, generation: usize) -> io::Result<()> {
+        let backup = self.read(&Self::generation_path(path, generation))?;
+        self.write(path, &backup)
+    }
+
+    /// Lists all existing backup generations of the file at `path` together with the time they
+    /// were last written, ordered from newest to oldest.
+    pub(crate) fn generations(&self, path: &Path) -> Vec<(usize, SystemTime)> {
+        (1..)
+            .map(|generation| (generation, Self::generation_path(path, generation)))
+            .map_while(|(generation, backup)| {
+                fs::metadata(backup)
+                    .and_then(|metadata| metadata.modified())
+                    .ok()
+                    .map(|modified| (generation, modified))
+            })
+            .collect()
+    }
+
+    /// Shifts every backup generation of `path` one step back, dropping the oldest one, and turns
+    /// the current file into generation 1. The current file is hard-linked instead of moved, so
+    /// that the live file is never missing.
+    fn rotate(&self, path: &Path) -> io::Result<()> {
+        if self.backups == 0 {
+            return Ok(());
+        }
+
+        let oldest = Self::generation_path(path, self.backups);
+        if oldest.exists() {
+            fs::remove_file(&oldest)?;
+        }
+
+        for generation in (1..self.backups).rev() {
+            let backup = Self::generation_path(path, generation);
+            if backup.exists() {
+                fs::rename(&backup, Self::generation_path(path, generation + 1))?;
+            }
+        }
+
+        let newest = Self::generation_path(path, 1);
+        if fs::hard_link(path, &newest).is_err() {
+            fs::copy(path, &newest)?;
+        }
+        Ok(())
+    }
+
+    /// Writes `data` to a temporary file next to `path` and syncs it to disk. Returns the path of
+    /// the temporary file.
+    fn write_temporary(path: &Path, data: &[u8]) -> io::Result<PathBuf> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let temporary_path = Self::sibling(path, "tmp");
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&temporary_path)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        Ok(temporary_path)
+    }
+
+    /// Path of the given backup generation of `path`.
+    fn generation_path(path: &Path, generation: usize) -> PathBuf {
+        Self::sibling(path, &generation.to_string())
+    }
+
+    /// Appends `.suffix` to the file name of `path`.
+    fn sibling(path: &Path, suffix: &str) -> PathBuf {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".");
+        name.push(suffix);
+        PathBuf::from(name)
+    }
+
+    /// Syncs the directory containing `path`, so the rename itself survives a crash. Not all
+    /// platforms allow opening directories, so failures are ignored.
+    fn sync_directory(path: &Path) {
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        if let Ok(directory) = File::open(directory) {
+            let _ = directory.sync_all();
+        }
+    }
+}
@@ -0,0 +1,294 @@
+use crate::database::{Database, ModelKind, TrainingParameters};
+use crate::evaluation::{Metrics, Split};
+use crate::recommender::Recommender;
+use crate::report::TrainingLog;
+use crate::storage::Storage;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The metric a hyperparameter search optimizes.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Objective {
+    Rmse,
+    Mae,
+    Auc,
+    Precision,
+    Recall,
+    Map,
+    Ndcg,
+    DebiasedRmse,
+    DebiasedRecall,
+}
+
+impl Objective {
+    /// Extracts the optimized value from evaluation metrics.
+    fn value(self, metrics: &Metrics) -> f64 {
+        match self {
+            Objective::Rmse => metrics.rmse,
+            Objective::Mae => metrics.mae,
+            Objective::Auc => metrics.auc,
+            Objective::Precision => metrics.precision,
+            Objective::Recall => metrics.recall,
+            Objective::Map => metrics.map,
+            Objective::Ndcg => metrics.ndcg,
+            Objective::DebiasedRmse => metrics.debiased_rmse,
+            Objective::DebiasedRecall => metrics.debiased_recall,
+        }
+    }
+
+    /// Orders values of this objective from best to worst. Undefined values are ordered last.
+    fn compare(self, a: f64, b: f64) -> std::cmp::Ordering {
+        match (a.is_nan(), b.is_nan()) {
+            (true, true) => std::cmp::Ordering::Equal,
+            (true, false) => std::cmp::Ordering::Greater,
+            (false, true) => std::cmp::Ordering::Less,
+            (false, false) => match self {
+                Objective::Rmse | Objective::Mae | Objective::DebiasedRmse => a.total_cmp(&b),
+                _ => b.total_cmp(&a),
+            },
+        }
+    }
+}
+
+/// How candidate hyperparameters are chosen from the search space.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Search {
+    /// Every combination of the given values is tried.
+    Grid,
+
+    /// The given number of candidates is sampled from the ranges spanned by the given values.
+    Random { trials: usize },
+}
+
+/// Values of the hyperparameters considered by the search.
+#[derive(Clone, Debug)]
+pub(crate) struct SearchSpace {
+    pub(crate) latent_factors: Vec<usize>,
+    pub(crate) iterations: Vec<usize>,
+    pub(crate) learning_rate: Vec<f64>,
+    pub(crate) regularization: Vec<f64>,
+}
+
+impl SearchSpace {
+    /// Generates the candidate hyperparameters. Parameters that are not searched are taken from
+    /// `base`. Random search samples the number of factors and iterations uniformly and the
+    /// learning rate and regularization log-uniformly, since their useful values span orders of
+    /// magnitude. Models other than factor models have none of the searched parameters, so
+    /// `base` is their only candidate.
+    pub(crate) fn candidates(
+        &self,
+        search: Search,
+        base: &TrainingParameters,
+        seed: u64,
+    ) -> Vec<TrainingParameters> {
+        if base.model != ModelKind::Factors {
+            return vec![*base];
+        }
+
+        match search {
+            Search::Grid => {
+                let mut candidates = Vec::new();
+                for &latent_factors in &self.latent_factors {
+                    for &iterations in &self.iterations {
+                        for &learning_rate in &self.learning_rate {
+                            for &regularization in &self.regularization {
+                                candidates.push(TrainingParameters {
+                                    latent_factors,
+                                    iterations,
+                                    learning_rate,
+                                    regularization,
+                                    ..*base
+                                });
+                            }
+                        }
+                    }
+                }
+                candidates
+            }
+            Search::Random { trials } => {
+                let mut rng = StdRng::seed_from_u64(seed);
+                let integer_range = |values: &[usize]| {
+                    *values.iter().min().unwrap()..=*values.iter().max().unwrap()
+                };
+                let log_range = |values: &[f64]| {
+                    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+                    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+                    min.ln()..=max.ln()
+                };
+
+                (0..trials)
+                    .map(|_| TrainingParameters {
+                        latent_factors: rng.gen_range(integer_range(&self.latent_factors)),
+                        iterations: rng.gen_range(integer_range(&self.iterations)),
+                        learning_rate: rng.gen_range(log_range(&self.learning_rate)).exp(),
+                        regularization: rng.gen_range(log_range(&self.regularization)).exp(),
+                        ..*base
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// The cross-validated performance of one candidate.
+#[derive(Clone, Debug)]
+pub(crate) struct TuningResult {
+    pub(crate) parameters: TrainingParameters,
+
+    /// Metrics averaged over all folds.
+    pub(crate) metrics: Metrics,
+}
+
+/// Evaluates all `candidates` with `folds`-fold cross-validation on `database`. All folds of all
+/// candidates are trained in parallel. Returns the results ordered from best to worst according
+/// to `objective`, with ranking metrics computed on the top `k` articles.
+pub(crate) fn tune(
+    database: &Database,
+    candidates: &[TrainingParameters],
+    folds: usize,
+    objective: Objective,
+    k: usize,
+    seed: u64,
+) -> Vec<TuningResult> {
+    let splits = Split::folds(database, folds, seed);
+    let jobs = candidates.len() * folds;
+    let finished = AtomicUsize::new(0);
+
+    let fold_metrics = (0..jobs)
+        .into_par_iter()
+        .map(|job| {
+            let (candidate, fold) = (job / folds, job % folds);
+            let (train, split) = splits[fold].clone();
+            let model = train.train_prediction_model(
+                &candidates[candidate],
+                split,
+                &TrainingLog::default(),
+            );
+            let metrics = Metrics::evaluate(
+                &model,
+                &model.held_out().validation,
+                k,
+                candidates[candidate].propensity.debiasing(),
+            );
+
+            let finished = finished.fetch_add(1, Ordering::Relaxed) + 1;
+            println!(
+                "Finished fold {}/{} of candidate {}/{} ({}/{} total).",
+                fold + 1,
+                folds,
+                candidate + 1,
+                candidates.len(),
+                finished,
+                jobs
+            );
+            metrics
+        })
+        .collect::<Vec<_>>();
+
+    let mut results = candidates
+        .iter()
+        .zip(fold_metrics.chunks(folds))
+        .map(|(&parameters, metrics)| TuningResult {
+            parameters,
+            metrics: Metrics::mean(metrics),
+        })
+        .collect::<Vec<_>>();
+
+    results.sort_by(|a, b| {
+        objective.compare(objective.value(&a.metrics), objective.value(&b.metrics))
+    });
+    results
+}
+
+/// Writes the tuning results as a CSV table to `path`. The table is replaced without backups,
+/// since the results of an earlier run are not worth keeping.
+pub(crate) fn write_results(storage: &Storage, path: &std::path::Path, results: &[TuningResult]) {
+    let mut table = String::from(
+        "latent_factors,iterations,learning_rate,regularization,rmse,mae,auc,precision,recall,map,ndcg,debiased_rmse,debiased_recall\n",
+    );
+    for result in results {
+        let (p, m) = (&result.parameters, &result.metrics);
+        writeln!(
+            table,
+            "{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            p.latent_factors,
+            p.iterations,
+            p.learning_rate,
+            p.regularization,
+            m.rmse,
+            m.mae,
+            m.auc,
+            m.precision,
+            m.recall,
+            m.map,
+            m.ndcg,
+            m.debiased_rmse,
+            m.debiased_recall
+        )
+        .unwrap();
+    }
+
+    storage
+        .replace(path, table.as_bytes())
+        .expect("Failed to write tuning results to file.");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn space() -> SearchSpace {
+        SearchSpace {
+            latent_factors: vec![10, 30],
+            iterations: vec![120],
+            learning_rate: vec![0.002, 0.008],
+            regularization: vec![0.01, 0.02, 0.05],
+        }
+    }
+
+    #[test]
+    fn grid_search_tries_every_combination() {
+        let candidates = space().candidates(Search::Grid, &TrainingParameters::default(), 0);
+        assert_eq!(candidates.len(), 2 * 2 * 3);
+        assert!(candidates.iter().any(|c| c.latent_factors == 30
+            && c.learning_rate == 0.002
+            && c.regularization == 0.05));
+    }
+
+    #[test]
+    fn random_search_samples_within_the_ranges() {
+        let search = Search::Random { trials: 50 };
+        let candidates = space().candidates(search, &TrainingParameters::default(), 0);
+        assert_eq!(candidates.len(), 50);
+        for candidate in candidates {
+            assert!((10..=30).contains(&candidate.latent_factors));
+            assert_eq!(candidate.iterations, 120);
+            assert!((0.002..=0.008 + 1e-12).contains(&candidate.learning_rate));
+            assert!((0.01..=0.05 + 1e-12).contains(&candidate.regularization));
+        }
+    }
+
+    #[test]
+    fn other_models_have_a_single_candidate() {
+        let base = TrainingParameters {
+            model: ModelKind::ItemKnn,
+            ..TrainingParameters::default()
+        };
+        let candidates = space().candidates(Search::Grid, &base, 0);
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].latent_factors, base.latent_factors);
+    }
+
+    #[test]
+    fn objectives_order_undefined_values_last() {
+        let mut values = [0.7, f64::NAN, 0.9];
+        values.sort_by(|&a, &b| Objective::Auc.compare(a, b));
+        assert_eq!(values[..2], [0.9, 0.7]);
+        values.sort_by(|&a, &b| Objective::Rmse.compare(a, b));
+        assert_eq!(values[..2], [0.7, 0.9]);
+        assert!(values[2].is_nan());
+    }
+}
@@ -1,13 +1,13 @@
-use std::fs::File;
-use std::str::FromStr;
-use std::time::Duration;
-use isahc::HttpClient;
+use crate::database::Database;
+use crate::storage::Storage;
 use isahc::config::RedirectPolicy;
 use isahc::cookies::CookieJar;
 use isahc::http::Uri;
 use isahc::prelude::*;
+use isahc::HttpClient;
 use scraper::{Html, Selector};
-use crate::database::Database;
+use std::str::FromStr;
+use std::time::Duration;
 
 const WIKI_URI: &str = "https://scp-wiki.wikidot.com/";
 const VOTE_ENDPOINT: &str = "https://scp-wiki.wikidot.com/ajax-module-connector.php";
@@ -18,6 +18,8 @@ const USER_AGENT: &str = "geyser-scp-vote-counter/0.2.0";
 pub(crate) struct Updater {
     /// The database builder.
     database: Database,
+    /// Where the database is loaded from and saved to.
+    storage: Storage,
     client: HttpClient,
     cookie_jar: CookieJar,
     head_selector: Selector,
@@ -25,11 +27,12 @@ pub(crate) struct Updater {
 }
 
 impl Updater {
-    fn new() -> Self {
+    fn new(storage: Storage) -> Self {
         let cookie_jar = CookieJar::new();
 
         Self {
             database: Database::new(),
+            storage,
             client: HttpClient::builder()
                 .timeout(Duration::from_secs(5))
                 .default_header("User-Agent", USER_AGENT)
@@ -45,17 +48,17 @@ impl Updater {
 
     /// Loads an updater from the database file. If there is no database file, a new updater is
     /// created.
-    pub(crate) fn load() -> Self {
-        let mut updater = Self::new();
-        if File::open("database.bin").is_ok() {
-            updater.database = Database::load();
+    pub(crate) fn load(storage: Storage) -> Self {
+        let mut updater = Self::new(storage);
+        if updater.storage.database_path.exists() {
+            updater.database = Database::load(&updater.storage);
         }
 
         updater
     }
 
-    /// Scrape SCP articles and user votes from the wiki without the API. Stores them in a
-    /// database file named ```database.bin```.
+    /// Scrape SCP articles and user votes from the wiki without the API. Stores them in the
+    /// database file configured in the [`Storage`].
     pub(crate) fn update(&mut self, from: usize, to: usize) {
         println!("Updating database...");
 
@@ -70,7 +73,12 @@ impl Updater {
         // is beyond me, since any session gets one automatically.
         println!("Obtaining wiki_token7...");
         self.client.head(WIKI_URI).unwrap();
-        let wiki_token7 = self.cookie_jar.get_by_name(&Uri::from_str(WIKI_URI).unwrap(), "wikidot_token7").unwrap().value().to_owned();
+        let wiki_token7 = self
+            .cookie_jar
+            .get_by_name(&Uri::from_str(WIKI_URI).unwrap(), "wikidot_token7")
+            .unwrap()
+            .value()
+            .to_owned();
         println!("wiki_token7: {}", wiki_token7);
 
         // for now this cannot handle non-scp-articles. It is easy to add by changing the for loop
@@ -111,7 +119,7 @@ impl Updater {
             };
 
             // parse vote answer
-            let answer = if let Some(answer) = json::parse(&votes).ok() {
+            let answer = if let Ok(answer) = json::parse(&votes) {
                 answer
             } else {
                 println!("Failed to parse vote answer for article {}", article_name);
@@ -124,8 +132,12 @@ impl Updater {
                 println!("Failed to extract vote body for article {}", article_name);
                 continue;
             };
-            let dom = Html::parse_document(&body);
-            let mut all_votes = dom.select(&div_selector).next().unwrap().select(&span_selector);
+            let dom = Html::parse_document(body);
+            let mut all_votes = dom
+                .select(&div_selector)
+                .next()
+                .unwrap()
+                .select(&span_selector);
 
             // extract votes from answer
             let mut votes = Vec::new();
@@ -148,7 +160,11 @@ impl Updater {
 
             // add article to database
             if article_present {
-                println!("Updated article {} with {} votes", article_name, votes.len());
+                println!(
+                    "Updated article {} with {} votes",
+                    article_name,
+                    votes.len()
+                );
                 self.database.update_article(article_name, votes);
             } else {
                 println!("added article to database with {} votes", votes.len());
@@ -157,7 +173,7 @@ impl Updater {
         }
 
         println!("Finished generating database. Saving to file...");
-        self.database.save();
+        self.database.save(&self.storage);
     }
 
     /// Make a request to the given url path and return the response body as a string.
@@ -168,7 +184,7 @@ impl Updater {
         self.client.get(url).map_or(None, |mut response| {
             if response.status().is_success() {
                 print!("success: ");
-                response.text().map_or(None, |text| Some(text))
+                response.text().ok()
             } else {
                 println!("failed: Error {}", response.status());
                 None
@@ -187,13 +203,15 @@ impl Updater {
             .append_pair("wikidot_token7", wiki_token7)
             .finish();
 
-        self.client.post(VOTE_ENDPOINT, request_body).map_or(None, |mut response| {
-            if response.status().is_success() {
-                response.text().map_or(None, |text| Some(text))
-            } else {
-                None
-            }
-        })
+        self.client
+            .post(VOTE_ENDPOINT, request_body)
+            .map_or(None, |mut response| {
+                if response.status().is_success() {
+                    response.text().ok()
+                } else {
+                    None
+                }
+            })
     }
 
     /// Extract the internal page id from the article by scraping it out of a javascript tag.
@@ -204,11 +222,21 @@ impl Updater {
             if script_tag.value().attr("src").is_none() {
                 let script_source = script_tag.first_child().unwrap().value().as_text().unwrap();
                 if script_source.contains("WIKIREQUEST.info.pageId") {
-                    return Some(u32::from_str_radix(script_source.split("WIKIREQUEST.info.pageId = ").nth(1).unwrap().split(';').next().unwrap(), 10).unwrap());
+                    return Some(
+                        script_source
+                            .split("WIKIREQUEST.info.pageId = ")
+                            .nth(1)
+                            .unwrap()
+                            .split(';')
+                            .next()
+                            .unwrap()
+                            .parse::<u32>()
+                            .unwrap(),
+                    );
                 }
             }
         }
 
         None
     }
-}
\ No newline at end of file
+}